@@ -1,34 +1,70 @@
 #![allow(dead_code)]
-use sfml::system::{Vector2f};
-use sfml::graphics::{RenderWindow, RenderTarget, CircleShape, Color, Transformable, Shape};
 use super::geometry::Circle;
 use super::math;
 use super::math::convert_vector2;
-use crate::vector_math::{angle_rad, rotate, dot_product};
+use crate::vector_math;
+use crate::vector_math::{Vector2, Vector2f, dot_product};
 use super::plane::Plane;
+use super::restitution::default_inertia_factor;
+use rand::Rng;
+
+
+// How far apart two bodies can be, or how far they can overlap, and still count as touching, as a
+// fraction of the radius involved. See contact_tolerance.
+const CONTACT_TOLERANCE: f64 = 1e-5;
+// Bodies already touching collide at once only if approaching faster than this fraction of their
+// relative speed, so that rounding error in a collision just resolved, particularly an inelastic one,
+// can't set off another at the same instant
+const APPROACH_TOLERANCE: f64 = 1e-6;
 
 
 #[derive(Debug, Copy, Clone)]
 pub struct Ball {
     pub circle:   Circle,
     pub velocity: Vector2f,
+    // In radians per unit time. Positive turns the x axis towards the y axis.
+    pub angular_velocity: f32,
+    // Angle of the ball, in radians, only used to show its spin
+    pub orientation: f32,
     mass: f32,
+    restitution: f32,
+    friction: f32,
+    // The moment of inertia as a fraction of mass * radius^2
+    inertia_factor: f32,
 }
 
 
-impl Ball {
-    pub fn draw(&self, window: &mut RenderWindow) {
-        let mut temp_circle = CircleShape::new(self.circle.radius, 32);
+// The impulse exchanged in a collision. The ball receives it at the point of contact, and the
+// other body the opposite.
+#[derive(Debug, Copy, Clone)]
+pub struct Impulse {
+    // Unit vector from the other body towards the ball, through the point of contact
+    pub direction: Vector2f,
+    // Along direction
+    pub normal: f32,
+    // Along direction rotated a quarter turn, in the sense of positive angular velocity
+    pub tangential: f32,
+}
+
 
-        temp_circle.set_fill_color(&Color::RED);
-        temp_circle.set_position(self.circle.position);
-        temp_circle.set_origin(Vector2f{x: temp_circle.radius(), y: temp_circle.radius()});
-        window.draw(&temp_circle);
+impl Impulse {
+    pub fn vector(&self) -> Vector2f {
+        self.direction * self.normal + Vector2f{x: -self.direction.y, y: self.direction.x} * self.tangential
     }
+}
+
 
-    pub fn default() -> Ball {
-        Ball{circle: Circle{position: Vector2f{x: 0., y: 0.}, radius: 16.0},
-             velocity: Vector2f{x: 0., y: 0.}, mass: 1.0}
+impl Default for Ball {
+    fn default() -> Ball {
+        Ball::new(Vector2f{x: 0., y: 0.}, Vector2f{x: 0., y: 0.}, 16.0, 1.0)
+    }
+}
+
+
+impl Ball {
+    pub fn new(position: Vector2f, velocity: Vector2f, radius: f32, mass: f32) -> Ball {
+        Ball{circle: Circle{position, radius}, velocity, angular_velocity: 0.0, orientation: 0.0,
+             mass, restitution: 1.0, friction: 0.0, inertia_factor: default_inertia_factor()}
     }
 
     pub fn get_mass(&self) -> f32 {
@@ -40,6 +76,62 @@ impl Ball {
         self.mass = m;
     }
 
+
+    pub fn get_restitution(&self) -> f32 {
+        self.restitution
+    }
+
+
+    pub fn set_restitution(&mut self, e: f32) {
+        self.restitution = e;
+    }
+
+
+    // The coefficient of friction
+    pub fn get_friction(&self) -> f32 {
+        self.friction
+    }
+
+
+    pub fn set_friction(&mut self, mu: f32) {
+        self.friction = mu;
+    }
+
+
+    pub fn get_inertia_factor(&self) -> f32 {
+        self.inertia_factor
+    }
+
+
+    // Sets the moment of inertia to k * mass * radius^2: 0.5 for a solid disk, 1 for a ring. With no
+    // moment of inertia (k of 0 or less), collisions can't change the ball's spin.
+    pub fn set_inertia_factor(&mut self, k: f32) {
+        self.inertia_factor = k;
+    }
+
+
+    pub fn get_inertia(&self) -> f32 {
+        self.inertia_factor * self.mass * self.circle.radius.powi(2)
+    }
+
+
+    // The reciprocal of the moment of inertia, or 0 for a ball without one, whose spin can't change
+    fn inverse_inertia(&self) -> f32 {
+        let inertia = self.get_inertia();
+        if inertia > 0.0 { 1.0 / inertia } else { 0.0 }
+    }
+
+
+    // Translational plus rotational
+    pub fn kinetic_energy(&self) -> f32 {
+        0.5 * self.mass * vector_math::length_squared(&self.velocity) + self.rotational_energy()
+    }
+
+
+    pub fn rotational_energy(&self) -> f32 {
+        0.5 * self.get_inertia() * self.angular_velocity.powi(2)
+    }
+
     pub fn get_position(&self) -> Vector2f {
         self.circle.position
     }
@@ -51,7 +143,22 @@ impl Ball {
 
 
     pub fn displace(&mut self, offset: &Vector2f) {
-        self.circle.position = self.circle.position + *offset;
+        self.circle.position += *offset;
+    }
+
+
+    // Moves and turns the ball as it would in free flight for time t, under a constant acceleration
+    pub fn advance(&mut self, t: f32, acceleration: Vector2f) {
+        self.displace(&(self.velocity * t + acceleration * (0.5 * t * t)));
+        self.velocity += acceleration * t;
+        self.orientation = (self.orientation + self.angular_velocity * t).rem_euclid(2.0 * std::f32::consts::PI);
+    }
+
+
+    // Changes the velocity and angular velocity as an impulse and angular impulse would
+    pub fn apply_impulse(&mut self, impulse: Vector2f, angular_impulse: f32) {
+        self.velocity += impulse / self.mass;
+        self.angular_velocity += angular_impulse * self.inverse_inertia();
     }
 
 
@@ -60,118 +167,406 @@ impl Ball {
     }
 
 
-    // Given two balls, presumed to be colliding, returns the post-collision velocities
-    // of ball a and ball b respectively
-    pub fn resolve_collision(a: & Ball, b: & Ball) -> (Vector2f, Vector2f) {
-        // We solve the problem in 2D by simply finding the axis of collision
-        // and then just solving the problem as a 1D collision along that axis.
-        // The velocity components perpendicular to the axis are unaffected.
-        let collision_axis = a.get_position() - b.get_position();
-        let axis_angle = angle_rad(&collision_axis);
-        let ua_loc = rotate(&a.velocity, -axis_angle);
-        let ub_loc = rotate(&b.velocity, -axis_angle);
+    // The speed at which two touching balls are approaching each other along the line between their centres
+    pub fn normal_speed(a: &Ball, b: &Ball) -> f32 {
+        let axis = a.get_position() - b.get_position();
+        let length = vector_math::length_squared(&axis).sqrt();
+        -dot_product(&(a.velocity - b.velocity), &axis) / length
+    }
 
-        let m_sum = a.get_mass() + b.get_mass();
-        let va_loc =  ua_loc.x * (a.get_mass() - b.get_mass()) / (m_sum)
-                          + ub_loc.x * (2.0 * b.get_mass()) / (m_sum);
-        let vb_loc = ua_loc.x * (2.0 * a.get_mass()) / (m_sum)
-                        + ub_loc.x * (a.get_mass() - b.get_mass()) / (a.get_mass() + b.get_mass());
 
-        let va = rotate(&Vector2f{x: va_loc, y: ua_loc.y} , axis_angle);
-        let vb = rotate(&Vector2f{x: vb_loc, y: ub_loc.y}, axis_angle);
-        (va, vb)
+    // Given two balls, presumed to be colliding, applies the collision to both and returns the impulse.
+    // The normal relative velocity is reversed and scaled by restitution, so 1 gives an elastic collision.
+    // The relative velocity of the surfaces at the contact point is likewise reversed and scaled by
+    // tangential_restitution (-1 leaves it unchanged, as for smooth disks), but only as far as Coulomb
+    // friction allows: the tangential impulse can't exceed friction times the normal impulse.
+    pub fn resolve_collision(a: &mut Ball, b: &mut Ball, restitution: f32, friction: f32,
+                             tangential_restitution: f32) -> Impulse {
+        let axis = a.get_position() - b.get_position();
+        let n = axis / vector_math::length_squared(&axis).sqrt();
+        let t = Vector2f{x: -n.y, y: n.x};
+
+        // Relative velocity of the surfaces at the point of contact
+        let spin = a.angular_velocity * a.circle.radius + b.angular_velocity * b.circle.radius;
+        let g = a.velocity - b.velocity - t * spin;
+
+        let inverse_mass = 1.0 / a.mass + 1.0 / b.mass;
+        let normal = -(1.0 + restitution) * dot_product(&g, &n) / inverse_mass;
+
+        let inverse_tangential_mass = inverse_mass + a.circle.radius.powi(2) * a.inverse_inertia()
+                                                   + b.circle.radius.powi(2) * b.inverse_inertia();
+        let tangential = tangential_impulse(normal, dot_product(&g, &t), inverse_tangential_mass,
+                                            friction, tangential_restitution);
+
+        let impulse = Impulse{direction: n, normal, tangential};
+        a.apply_impulse(impulse.vector(), -a.circle.radius * tangential);
+        b.apply_impulse(-impulse.vector(), -b.circle.radius * tangential);
+        impulse
     }
 
 
     // Given two balls, returns the time until they will collide or None if they are not going
-    // to collide in future (in the past if invert_time is set true)
-    pub fn collision_time(ball1: &Ball, ball2: &Ball, invert_time: bool) -> Option<f32> {
-        // If the balls are intersecting, we consider them as not going to collide.
-        // Insert the reason here when less fucking tired.
-        if ball1.intersects(ball2) {
+    // to collide in future (in the past if invert_time is set true). The balls have constant
+    // accelerations a1 and a2. Balls touching to within contact_tolerance and approaching collide at
+    // time 0; balls overlapping by more than that, as soft balls can, pass through each other.
+    pub fn collision_time(ball1: &Ball, ball2: &Ball, a1: Vector2f, a2: Vector2f, invert_time: bool) -> Option<f32> {
+        // Relative quantities, in f64 before subtracting, so that nothing cancels far from the origin
+        let p = convert_vector2::<f32, f64>(ball1.get_position()) - convert_vector2::<f32, f64>(ball2.get_position());
+        let v = convert_vector2::<f32, f64>(ball1.velocity) - convert_vector2::<f32, f64>(ball2.velocity);
+        let q = (convert_vector2::<f32, f64>(a1) - convert_vector2::<f32, f64>(a2)) * 0.5;
+        let r = ball1.circle.radius as f64 + ball2.circle.radius as f64;
+
+        let dot = |a: Vector2<f64>, b: Vector2<f64>| a.x * b.x + a.y * b.y;
+        let distance = dot(p, p).sqrt();
+        let gap = distance - r;
+        let tolerance = contact_tolerance(r, ball1.get_position());
+        if gap < -tolerance {
             return None;
         }
 
-        // We approach this by finding the roots of a quadratic function of dt
-        let u1 = convert_vector2::<f32, f64>(ball1.velocity);
-        let u2 = convert_vector2::<f32, f64>(ball2.velocity);
-        let i1 = convert_vector2::<f32, f64>(ball1.get_position());  // initial position
-        let i2 = convert_vector2::<f32, f64>(ball2.get_position());  // initial position
-        let r1 = ball1.circle.radius as f64;
-        let r2 = ball2.circle.radius as f64;
-
-        // The coefficients for the quadratic formula:
-        let a = (u1.x - u2.x).powi(2) + (u1.y - u2.y).powi(2);
-        let b = 2.0 * ( (i1.x - i2.x)*(u1.x - u2.x) + (i1.y - i2.y)*(u1.y - u2.y) );
-        let c = -2.0 * (i1.x*i2.x + i1.y*i2.y) + i1.x.powi(2) + i2.x.powi(2) +
-                    i1.y.powi(2) + i2.y.powi(2) - (r1+r2).powi(2);
-
-        let result = match math::find_roots(a, b, c) {
-            Some((dt1, dt2)) => {
-                // If both are positive then will be the smallest one,
-                // as the larger will represent the balls touching but
-                // on the other side.
-                let (min, max) = if dt1 < dt2 {(dt1, dt2)} else {(dt2, dt1)};
-                if invert_time {
-                    if max <= 0.0 {
-                        Some(max)
-                    } else if min <= 0.0 {
-                        Some(min)
-                    }
-                    else {
-                        None
-                    }
-                }
-                else {
-                    if min >= 0.0 {
-                        Some(min)
-                    } else if max >= 0.0 {
-                        Some(max)
-                    } else {
-                        None
-                    }
-                }
-            },
-            None => None
-        };
-
-        match result {
-            Some(t) => {
-                Some(t as f32)
-            },
-            None => {
-                None
-            }
-        }
+        // The squared distance less r^2 is a quadratic in time, or a quartic under relative
+        // acceleration. Its constant term is written so as not to cancel when the balls are close.
+        let roots = math::polynomial_roots(&[dot(q, q), 2.0 * dot(q, v), dot(v, v) + 2.0 * dot(q, p),
+                                             2.0 * dot(p, v), gap * (distance + r)]);
+        // The rate of change of the squared distance has the sign of the relative position
+        // dotted with the relative velocity
+        first_contact(&roots, gap.abs() <= tolerance, distance * dot(v, v).sqrt(),
+                      |t| dot(p + v * t + q * (t * t), v + q * (2.0 * t)), invert_time)
     }
 
 
-    pub fn resolve_plane_collision(&mut self, plane: &Plane) {
+    // As resolve_collision, with the plane as an immovable body
+    pub fn resolve_plane_collision(&mut self, plane: &Plane, restitution: f32, friction: f32,
+                                   tangential_restitution: f32) -> Impulse {
+        let n = plane.normal;
+        let t = Vector2f{x: -n.y, y: n.x};
+        let g = self.velocity - t * (self.angular_velocity * self.circle.radius);
+
         // v projected onto the plane normal
-        let v = dot_product(&plane.normal, &self.velocity);
-        self.velocity = self.velocity - plane.normal * 2.0 * v;
+        let v = dot_product(&n, &g);
+        let normal = -(1.0 + restitution) * v * self.mass;
+
+        let inverse_tangential_mass = 1.0 / self.mass + self.circle.radius.powi(2) * self.inverse_inertia();
+        let tangential = tangential_impulse(normal, dot_product(&g, &t), inverse_tangential_mass,
+                                            friction, tangential_restitution);
+
+        let impulse = Impulse{direction: n, normal, tangential};
+        self.apply_impulse(impulse.vector(), -self.circle.radius * tangential);
+        impulse
     }
 
 
-    pub fn plane_collision_time(&self, plane: &Plane, invert_time: bool) -> Option<f32> {
-        let v = dot_product(&plane.normal, &self.velocity);
-        if v == 0.0 {
+    // Sends the ball off a heat bath at the given temperature as if the wall had absorbed it and
+    // emitted a new one: the velocity is drawn afresh from those with which balls in equilibrium at
+    // that temperature cross a plane, so the normal speed is Rayleigh distributed and the tangential
+    // speed normally distributed. Spin is kept. Boltzmann's constant is taken as 1. When time runs
+    // backwards the ball leaves along -n, so that stepping back moves it away from the wall.
+    pub fn resolve_thermal_collision<R: Rng>(&mut self, plane: &Plane, temperature: f32, invert_time: bool,
+                                             rng: &mut R) -> Impulse {
+        let n = if invert_time {-plane.normal} else {plane.normal};
+        let t = Vector2f{x: -n.y, y: n.x};
+        let sigma = (temperature / self.mass).sqrt();
+        let normal_speed = sigma * (-2.0 * (1.0 - rng.gen::<f32>()).ln()).sqrt();
+        let tangential_speed = sigma * math::standard_normal(rng);
+
+        let change = n * normal_speed + t * tangential_speed - self.velocity;
+        self.velocity += change;
+        Impulse{direction: n, normal: self.mass * dot_product(&change, &n), tangential: self.mass * dot_product(&change, &t)}
+    }
+
+
+    // Every time, in the direction of time, at which the ball comes to touch the plane from the side
+    // its normal faces. There can be two under acceleration: it may pass through and come back.
+    pub fn plane_contact_times(&self, plane: &Plane, acceleration: Vector2f, invert_time: bool) -> Vec<f32> {
+        let (gap, roots, rate) = self.plane_gap(plane, acceleration);
+        let touching = gap.abs() <= contact_tolerance(self.circle.radius as f64, self.get_position());
+        contacts(&roots, touching, vector_math::length_squared(&self.velocity).sqrt() as f64, rate, invert_time)
+    }
+
+
+    // Every time, in the direction of time, at which the ball comes to touch a circle, from the inside
+    // if inside is true and otherwise from the outside
+    pub fn circle_contact_times(&self, centre: Vector2f, radius: f32, acceleration: Vector2f, inside: bool,
+                                invert_time: bool) -> Vec<f32> {
+        let p = convert_vector2::<f32, f64>(self.get_position()) - convert_vector2::<f32, f64>(centre);
+        let v = convert_vector2::<f32, f64>(self.velocity);
+        let q = convert_vector2::<f32, f64>(acceleration * 0.5);
+        let reach = if inside {radius as f64 - self.circle.radius as f64} else {radius as f64 + self.circle.radius as f64};
+        if reach <= 0.0 {
+            return Vec::new();
+        }
+
+        let dot = |a: Vector2<f64>, b: Vector2<f64>| a.x * b.x + a.y * b.y;
+        let distance = dot(p, p).sqrt();
+        let roots = math::polynomial_roots(&[dot(q, q), 2.0 * dot(q, v), dot(v, v) + 2.0 * dot(q, p),
+                                             2.0 * dot(p, v), (distance - reach) * (distance + reach)]);
+        let touching = (distance - reach).abs() <= contact_tolerance(self.circle.radius as f64, self.get_position());
+        // From the inside the gap closes as the distance from the centre grows
+        let sign = if inside {-1.0} else {1.0};
+        contacts(&roots, touching, distance * dot(v, v).sqrt(),
+                 |t| sign * dot(p + v * t + q * (t * t), v + q * (2.0 * t)), invert_time)
+    }
+
+
+    // As collision_time, for the ball with constant acceleration hitting the plane. A ball that has
+    // passed through the plane by more than contact_tolerance doesn't hit it.
+    pub fn plane_collision_time(&self, plane: &Plane, acceleration: Vector2f, invert_time: bool) -> Option<f32> {
+        let (gap, roots, rate) = self.plane_gap(plane, acceleration);
+        let tolerance = contact_tolerance(self.circle.radius as f64, self.get_position());
+        if gap < -tolerance {
             return None;
         }
+        first_contact(&roots, gap <= tolerance, vector_math::length_squared(&self.velocity).sqrt() as f64, rate, invert_time)
+    }
+
+
+    // The distance from the plane to the ball's surface, the times it is zero, which is a quadratic in
+    // time (or linear without acceleration normal to the plane), and the rate at which it is changing
+    fn plane_gap(&self, plane: &Plane, acceleration: Vector2f) -> (f64, Vec<f64>, impl Fn(f64) -> f64) {
+        let v = dot_product(&plane.normal, &self.velocity) as f64;
+        let a = dot_product(&plane.normal, &acceleration) as f64;
+        let offset = convert_vector2::<f32, f64>(self.get_position()) - convert_vector2::<f32, f64>(plane.position);
+        let gap = plane.normal.x as f64 * offset.x + plane.normal.y as f64 * offset.y - self.circle.radius as f64;
+        (gap, math::polynomial_roots(&[0.5 * a, v, gap]), move |t| v + a * t)
+    }
+}
+
 
-        let initial_pos = dot_product(&plane.normal, &(self.get_position() - plane.position));
-        // the position, in plane-space, at which the ball is touching the plane
-        let final_pos = self.circle.radius;
-        // The displacement from the ball's current position at which it hits the plane
-        let s = final_pos - initial_pos;
+// Within how far of touching, apart or overlapping, bodies count as touching: a small fraction of the
+// radius involved, plus several times the rounding error in positions around position, which
+// collisions resolved in f32 can leave bodies apart or overlapping by
+fn contact_tolerance(radius: f64, position: Vector2f) -> f64 {
+    let scale = position.x.abs().max(position.y.abs()) as f64;
+    CONTACT_TOLERANCE * radius + 4.0 * f32::EPSILON as f64 * scale
+}
+
+
+// Of the times at which two bodies are touching, those in the direction of time at which they
+// are approaching, given the rate at which the gap between them is changing. Bodies touching now,
+// whose relative speed is speed, are in contact at time 0 if approaching clearly enough; a root near 0
+// that rounding puts in the direction of time is kept only if they are approaching there.
+fn contacts<F: Fn(f64) -> f64>(roots: &[f64], touching: bool, speed: f64, rate: F, invert_time: bool) -> Vec<f32> {
+    let direction = if invert_time {-1.0} else {1.0};
+    let mut times = Vec::new();
+    if touching && rate(0.0) * direction < -APPROACH_TOLERANCE * speed {
+        times.push(0.0);
+    }
+    times.extend(roots.iter()
+        .filter(|t| **t * direction > 0.0 && rate(**t) * direction < 0.0)
+        .map(|t| *t as f32));
+    times
+}
+
+
+// The soonest of contacts
+fn first_contact<F: Fn(f64) -> f64>(roots: &[f64], touching: bool, speed: f64, rate: F, invert_time: bool) -> Option<f32> {
+    contacts(roots, touching, speed, rate, invert_time).into_iter().min_by(|a, b| a.abs().total_cmp(&b.abs()))
+}
+
+
+// The tangential impulse that scales the tangential contact velocity gt by -tangential_restitution,
+// limited by Coulomb friction
+fn tangential_impulse(normal: f32, gt: f32, inverse_mass: f32, friction: f32, tangential_restitution: f32) -> f32 {
+    let sticking = -(1.0 + tangential_restitution) * gt / inverse_mass;
+    let limit = friction * normal.abs();
+    sticking.max(-limit).min(limit)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand::rngs::StdRng;
+
+    fn random_vector(rng: &mut StdRng, size: f32) -> Vector2f {
+        Vector2f{x: rng.gen_range(-size, size), y: rng.gen_range(-size, size)}
+    }
 
-        let t = s / v;
 
-        if t >= 0.0 {
-            if invert_time {None} else {Some(t)}
+    // The distance between the balls' centres after time t, in f64
+    fn distance_after(ball1: &Ball, ball2: &Ball, t: f64) -> f64 {
+        let p = convert_vector2::<f32, f64>(ball1.get_position()) - convert_vector2::<f32, f64>(ball2.get_position());
+        let v = convert_vector2::<f32, f64>(ball1.velocity) - convert_vector2::<f32, f64>(ball2.velocity);
+        let q = p + v * t;
+        (q.x * q.x + q.y * q.y).sqrt()
+    }
+
+
+    #[test]
+    fn collision_time_is_first_contact() {
+        let mut rng = StdRng::seed_from_u64(3);
+        for i in 0..100_000 {
+            // Some pairs far from the origin, where positions are coarse
+            let offset = if i % 4 == 0 { random_vector(&mut rng, 1e5) } else { Vector2f::default() };
+            let ball1 = Ball::new(offset + random_vector(&mut rng, 100.0), random_vector(&mut rng, 500.0), rng.gen_range(0.5, 20.0), 1.0);
+            let ball2 = Ball::new(offset + random_vector(&mut rng, 100.0), random_vector(&mut rng, 500.0), rng.gen_range(0.5, 20.0), 1.0);
+            let r = (ball1.circle.radius + ball2.circle.radius) as f64;
+            let tolerance = contact_tolerance(r, ball1.get_position());
+            if distance_after(&ball1, &ball2, 0.0) < r + tolerance {
+                continue;
+            }
+
+            match Ball::collision_time(&ball1, &ball2, Vector2f::default(), Vector2f::default(), false) {
+                Some(t) => {
+                    let t = t as f64;
+                    assert!(t >= 0.0);
+                    assert!((distance_after(&ball1, &ball2, t) - r).abs() <= tolerance, "not touching at {}", t);
+                    for k in 0..10 {
+                        assert!(distance_after(&ball1, &ball2, t * k as f64 / 10.0) >= r - tolerance, "overlapped before {}", t);
+                    }
+
+                    // The same collision, in the past of the balls moving the other way
+                    let (mut back1, mut back2) = (ball1, ball2);
+                    back1.velocity = -back1.velocity;
+                    back2.velocity = -back2.velocity;
+                    let back = Ball::collision_time(&back1, &back2, Vector2f::default(), Vector2f::default(), true);
+                    assert_eq!(back.map(|t| -t as f64), Some(t));
+                },
+                None => {
+                    // The closest approach in future is no closer than touching
+                    let p = convert_vector2::<f32, f64>(ball1.get_position()) - convert_vector2::<f32, f64>(ball2.get_position());
+                    let v = convert_vector2::<f32, f64>(ball1.velocity) - convert_vector2::<f32, f64>(ball2.velocity);
+                    let closest = (-(p.x * v.x + p.y * v.y) / (v.x * v.x + v.y * v.y)).max(0.0);
+                    if closest.is_finite() {
+                        assert!(distance_after(&ball1, &ball2, closest) >= r - tolerance, "missed a collision");
+                    }
+                },
+            }
         }
-        else {
-            if invert_time {Some(t)} else {None}
+    }
+
+
+    #[test]
+    fn collision_time_edge_cases() {
+        let none = Vector2f::default();
+        let at = |x: f32, y: f32, vx: f32, vy: f32| Ball::new(Vector2f{x, y}, Vector2f{x: vx, y: vy}, 1.0, 1.0);
+
+        // No relative motion
+        assert_eq!(Ball::collision_time(&at(0.0, 0.0, 3.0, 4.0), &at(5.0, 0.0, 3.0, 4.0), none, none, false), None);
+        // Head on
+        assert_eq!(Ball::collision_time(&at(0.0, 0.0, 1.0, 0.0), &at(4.0, 0.0, -1.0, 0.0), none, none, false), Some(1.0));
+        // Grazing: passing exactly at touching distance
+        let grazing = Ball::collision_time(&at(0.0, 0.0, 1.0, 0.0), &at(10.0, 2.0, 0.0, 0.0), none, none, false);
+        assert!(grazing.is_none_or(|t| (t - 10.0).abs() < 1e-3), "{:?}", grazing);
+        // Touching and approaching collide at once, touching and separating don't
+        assert_eq!(Ball::collision_time(&at(0.0, 0.0, 1.0, 0.0), &at(2.0, 0.0, 0.0, 0.0), none, none, false), Some(0.0));
+        assert_eq!(Ball::collision_time(&at(0.0, 0.0, -1.0, 0.0), &at(2.0, 0.0, 0.0, 0.0), none, none, false), None);
+        // Overlapping by rounding error, as a just resolved collision can leave them, and approaching
+        assert_eq!(Ball::collision_time(&at(0.0, 0.0, 1.0, 0.0), &at(1.999_999_9, 0.0, 0.0, 0.0), none, none, false), Some(0.0));
+        // Overlapping deeply, as soft balls can
+        assert_eq!(Ball::collision_time(&at(0.0, 0.0, 1.0, 0.0), &at(1.0, 0.0, 0.0, 0.0), none, none, false), None);
+        // Touching and sliding past with an approach speed only rounding error could give
+        assert_eq!(Ball::collision_time(&at(0.0, 0.0, 1e-9, 1.0), &at(2.0, 0.0, 0.0, 0.0), none, none, false), None);
+        // Touching under acceleration towards each other
+        let a = Vector2f{x: 1.0, y: 0.0};
+        assert_eq!(Ball::collision_time(&at(0.0, 0.0, 1.0, 0.0), &at(2.0, 0.0, 0.0, 0.0), a, none, false), Some(0.0));
+    }
+
+
+    #[test]
+    fn plane_collision_time_edge_cases() {
+        let plane = Plane::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 0.0, y: 1.0});
+        let none = Vector2f::default();
+        let at = |y: f32, vy: f32| Ball::new(Vector2f{x: 1e4, y}, Vector2f{x: 3.0, y: vy}, 1.0, 1.0);
+
+        assert_eq!(at(3.0, -1.0).plane_collision_time(&plane, none, false), Some(2.0));
+        assert_eq!(at(3.0, 1.0).plane_collision_time(&plane, none, false), None);
+        assert_eq!(at(3.0, 0.0).plane_collision_time(&plane, none, false), None);
+        assert_eq!(at(3.0, 1.0).plane_collision_time(&plane, none, true), Some(-2.0));
+        // Just past the plane, by rounding error, still bounces rather than tunnelling through
+        assert_eq!(at(0.999_9, -1.0).plane_collision_time(&plane, none, false), Some(0.0));
+        assert_eq!(at(0.999_9, 1.0).plane_collision_time(&plane, none, false), None);
+        // Well past it, the ball is on the other side
+        assert_eq!(at(0.5, -1.0).plane_collision_time(&plane, none, false), None);
+        // Thrown up and falling back
+        let gravity = Vector2f{x: 0.0, y: -2.0};
+        assert_eq!(at(1.0, 2.0).plane_collision_time(&plane, gravity, false), Some(2.0));
+    }
+
+
+    #[test]
+    fn rough_elastic_collision_conserves_energy_and_angular_momentum() {
+        let mut rng = StdRng::seed_from_u64(8);
+        let cross = |a: Vector2f, b: Vector2f| a.x * b.y - a.y * b.x;
+        for _ in 0..100 {
+            let mut a = Ball::new(Vector2f::default(), random_vector(&mut rng, 10.0), rng.gen_range(0.5, 2.0),
+                                  rng.gen_range(0.5, 2.0));
+            let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+            let radius = rng.gen_range(0.5, 2.0);
+            let direction = Vector2f{x: angle.cos(), y: angle.sin()};
+            let mut b = Ball::new(direction * (a.circle.radius + radius), random_vector(&mut rng, 10.0), radius,
+                                  rng.gen_range(0.5, 2.0));
+            a.angular_velocity = rng.gen_range(-5.0, 5.0);
+            b.angular_velocity = rng.gen_range(-5.0, 5.0);
+            b.set_inertia_factor(1.0);
+            if Ball::normal_speed(&a, &b) <= 0.0 {
+                continue;
+            }
+
+            let contact = direction * a.circle.radius;
+            let angular_momentum = |a: &Ball, b: &Ball| {
+                [a, b].iter().map(|ball| ball.get_mass() * cross(ball.get_position() - contact, ball.velocity)
+                                         + ball.get_inertia() * ball.angular_velocity).sum::<f32>()
+            };
+            let energy = a.kinetic_energy() + b.kinetic_energy();
+            let momentum = angular_momentum(&a, &b);
+
+            // Friction high enough that the surfaces never slip
+            Ball::resolve_collision(&mut a, &mut b, 1.0, 100.0, 1.0);
+            let scale = 1.0 + energy;
+            assert!((a.kinetic_energy() + b.kinetic_energy() - energy).abs() < 1e-4 * scale);
+            assert!((angular_momentum(&a, &b) - momentum).abs() < 1e-4 * scale);
         }
     }
+
+
+    #[test]
+    fn spinning_ball_leaves_a_wall_rolling() {
+        // A solid disk spinning at 6 and falling onto the floor at 10, with friction enough to stop it
+        // slipping: it leaves rolling, with a third of its spin and the surface speed that matches it
+        let floor = Plane::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 0.0, y: 1.0});
+        let mut ball = Ball::new(Vector2f{x: 0.0, y: 2.0}, Vector2f{x: 0.0, y: -10.0}, 2.0, 1.0);
+        ball.angular_velocity = 6.0;
+        let impulse = ball.resolve_plane_collision(&floor, 1.0, 10.0, 0.0);
+        assert_eq!(impulse.normal, 20.0);
+        assert!((impulse.tangential - 4.0).abs() < 1e-5);
+        assert!((ball.velocity.x + 4.0).abs() < 1e-5);
+        assert_eq!(ball.velocity.y, 10.0);
+        assert!((ball.angular_velocity - 2.0).abs() < 1e-5);
+    }
+
+
+    #[test]
+    fn friction_caps_the_tangential_impulse() {
+        // As above, but sticking would need a tangential impulse of 4 and friction allows 0.1 * 20
+        let floor = Plane::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 0.0, y: 1.0});
+        let mut ball = Ball::new(Vector2f{x: 0.0, y: 2.0}, Vector2f{x: 0.0, y: -10.0}, 2.0, 1.0);
+        ball.angular_velocity = 6.0;
+        let impulse = ball.resolve_plane_collision(&floor, 1.0, 0.1, 0.0);
+        assert!((impulse.tangential - 2.0).abs() < 1e-5);
+        assert!((ball.velocity.x + 2.0).abs() < 1e-5);
+        assert!((ball.angular_velocity - 4.0).abs() < 1e-5);
+
+        // Between two balls, the cap is the same
+        let mut a = Ball::new(Vector2f{x: 0.0, y: 2.0}, Vector2f{x: 0.0, y: -10.0}, 1.0, 1.0);
+        let mut b = Ball::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 0.0, y: 0.0}, 1.0, 1.0);
+        a.angular_velocity = 20.0;
+        let impulse = Ball::resolve_collision(&mut a, &mut b, 1.0, 0.1, 0.0);
+        assert!((impulse.tangential.abs() - 0.1 * impulse.normal.abs()).abs() < 1e-5);
+    }
+
+
+    #[test]
+    fn ball_without_inertia_keeps_its_spin() {
+        let floor = Plane::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 0.0, y: 1.0});
+        let mut ball = Ball::new(Vector2f{x: 0.0, y: 2.0}, Vector2f{x: 3.0, y: -10.0}, 2.0, 1.0);
+        ball.angular_velocity = 6.0;
+        ball.set_inertia_factor(0.0);
+        ball.resolve_plane_collision(&floor, 1.0, 10.0, 0.0);
+        assert_eq!(ball.angular_velocity, 6.0);
+        // The surface stops slipping through the change in velocity alone: 3 + 6 * 2 = 15 becomes 0
+        assert!((ball.velocity.x + 12.0).abs() < 1e-5);
+        assert_eq!(ball.velocity.y, 10.0);
+    }
 }
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use super::vector_math::Vector2f;
+use super::ball::Ball;
+use super::field::Field;
+use super::periodic::Periodic;
+
+
+// How World decides which pairs of balls are worth testing for a collision
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BroadPhase {
+    // Test every pair of balls
+    BruteForce,
+    // Only test pairs whose swept circles share a cell of a uniform grid. If cell_size is None
+    // a size is chosen from the ball radii and speeds each time the grid is built.
+    Grid { cell_size: Option<f32> },
+}
+
+
+// A uniform grid of square cells, each holding the indices of the balls whose
+// swept bounding boxes overlap it. With periodic boundaries, boxes crossing an edge of the periodic
+// cell are also entered, and looked up, on the other side.
+pub struct SpatialGrid {
+    cell_size: f32,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+    periodic: Periodic,
+}
+
+
+impl SpatialGrid {
+    pub fn new(cell_size: f32) -> SpatialGrid {
+        assert!(cell_size > 0.0, "grid cell size must be positive");
+        SpatialGrid{cell_size, cells: HashMap::new(), periodic: Periodic::default()}
+    }
+
+
+    // Builds a grid containing every ball, swept over the time dt (which may be negative)
+    pub fn from_balls(balls: &[Ball], field: &Field, periodic: &Periodic, dt: f32, cell_size: Option<f32>) -> SpatialGrid {
+        let cell_size = cell_size.unwrap_or_else(|| SpatialGrid::auto_cell_size(balls, dt));
+        let mut grid = SpatialGrid::with_periodic(cell_size, *periodic);
+        for (i, ball) in balls.iter().enumerate() {
+            let (min, max) = SpatialGrid::swept_bounds(ball, field.acceleration(ball), dt);
+            grid.insert(i, min, max);
+        }
+        grid
+    }
+
+
+    pub fn with_periodic(cell_size: f32, periodic: Periodic) -> SpatialGrid {
+        SpatialGrid{periodic, ..SpatialGrid::new(cell_size)}
+    }
+
+
+    // A cell a little larger than the biggest ball plus the average distance travelled,
+    // so that most balls only touch a handful of cells.
+    pub fn auto_cell_size(balls: &[Ball], dt: f32) -> f32 {
+        if balls.is_empty() {
+            return 1.0;
+        }
+        let max_diameter = balls.iter().map(|b| 2.0 * b.circle.radius).fold(0.0, f32::max);
+        let mean_travel = balls.iter()
+            .map(|b| (b.velocity.x.abs() + b.velocity.y.abs()) * dt.abs())
+            .sum::<f32>() / balls.len() as f32;
+        (max_diameter + mean_travel).max(1.0)
+    }
+
+
+    // The axis-aligned box containing the ball over the whole of the time dt, under a constant acceleration
+    pub fn swept_bounds(ball: &Ball, acceleration: Vector2f, dt: f32) -> (Vector2f, Vector2f) {
+        let p = ball.get_position();
+        let v = ball.velocity;
+        let (x0, x1) = axis_range(p.x, v.x, acceleration.x, dt);
+        let (y0, y1) = axis_range(p.y, v.y, acceleration.y, dt);
+        let r = ball.circle.radius;
+        (Vector2f{x: x0 - r, y: y0 - r}, Vector2f{x: x1 + r, y: y1 + r})
+    }
+
+
+    pub fn insert(&mut self, index: usize, min: Vector2f, max: Vector2f) {
+        for offset in self.periodic.image_offsets(min, max) {
+            self.insert_box(index, min + offset, max + offset);
+        }
+        self.insert_box(index, min, max);
+    }
+
+
+    fn insert_box(&mut self, index: usize, min: Vector2f, max: Vector2f) {
+        let (x0, y0) = self.cell_of(min);
+        let (x1, y1) = self.cell_of(max);
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                self.cells.entry((x, y)).or_default().push(index);
+            }
+        }
+    }
+
+
+    // The sorted indices of every ball sharing a cell with the given box
+    pub fn query(&self, min: Vector2f, max: Vector2f) -> Vec<usize> {
+        let mut found = Vec::new();
+        let offsets = self.periodic.image_offsets(min, max);
+        for offset in offsets.iter().chain(&[Vector2f::default()]) {
+            let (x0, y0) = self.cell_of(min + *offset);
+            let (x1, y1) = self.cell_of(max + *offset);
+            for x in x0..=x1 {
+                for y in y0..=y1 {
+                    if let Some(indices) = self.cells.get(&(x, y)) {
+                        found.extend_from_slice(indices);
+                    }
+                }
+            }
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+    }
+
+
+    // Every pair (i, j) with i < j that shares at least one cell, sorted and without duplicates
+    pub fn pairs(&self) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        for indices in self.cells.values() {
+            for (n, &i) in indices.iter().enumerate() {
+                for &j in &indices[n + 1..] {
+                    if i != j {
+                        pairs.push((i.min(j), i.max(j)));
+                    }
+                }
+            }
+        }
+        pairs.sort_unstable();
+        pairs.dedup();
+        pairs
+    }
+
+
+    fn cell_of(&self, point: Vector2f) -> (i32, i32) {
+        ((point.x / self.cell_size).floor() as i32, (point.y / self.cell_size).floor() as i32)
+    }
+}
+
+
+// The least and greatest of p + v t + a t^2 / 2 for t between 0 and dt
+fn axis_range(p: f32, v: f32, a: f32, dt: f32) -> (f32, f32) {
+    let at = |t: f32| p + v * t + 0.5 * a * t * t;
+    let end = at(dt);
+    let (mut min, mut max) = (p.min(end), p.max(end));
+    // The turning point, if it is passed during the step
+    if a != 0.0 {
+        let turn = -v / a;
+        if turn * dt > 0.0 && turn.abs() < dt.abs() {
+            min = min.min(at(turn));
+            max = max.max(at(turn));
+        }
+    }
+    (min, max)
+}
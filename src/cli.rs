@@ -0,0 +1,240 @@
+// Command line options:
+//
+//   particle_sim [--scene PATH] [--seed N] [--headless] [--steps N | --time T] [--dt DT]
+//                [--output PATH] [--save-scene PATH] [--check-conservation TOL | --assert-conservation TOL]
+//                [--histogram-bins N] [--histogram-window N] [--readout-unit "NAME=SCALE UNIT"]...
+//                [--log PATH [--log-interval T] [--log-observables NAMES]] [--record PATH [--record-frames]]
+//                [--play PATH] [--reverse-and-return]
+//
+// --scene loads the world from a scene file instead of using the default one. --seed fixes the
+// random numbers used to generate the world, overriding any seed in the scene; without it one is
+// picked at random. --save-scene writes the world as it is at the end of a headless run back out as a scene.
+// --check-conservation warns, and --assert-conservation panics, if the relative drift in energy or
+// momentum exceeds TOL. The viewer's speed histogram has --histogram-bins bins and averages over
+// the last --histogram-window frames. --readout-unit shows the viewer's readout called NAME multiplied
+// by SCALE and followed by UNIT, for example "mean free time=1000 ms"; it can be given more than once.
+// --log writes observables to a CSV file every --log-interval of simulated time (0.1 by default),
+// at the end of the step in which each sample falls due, in headless runs and in the viewer. --log-observables picks which, as a comma separated list of
+// names such as "energy,momentum,wall pressure,collisions,temperature", which is the default.
+// --record writes the run to a trajectory file, as collisions or, with --record-frames, as the state
+// of every ball after each update. --play shows a trajectory file in the viewer instead of simulating,
+// with the time slider scrubbing through it. --reverse-and-return runs headless forwards for the
+// duration and then backwards for it, and writes how far each ball ends up from its start to --output.
+// Without the gui feature every run is headless.
+use particle_sim::ConservationCheck;
+use particle_sim::observables;
+
+
+pub struct Options {
+    pub headless: bool,
+    pub duration: Duration,
+    pub dt: f32,
+    pub output: String,
+    pub scene: Option<String>,
+    pub save_scene: Option<String>,
+    pub seed: Option<u64>,
+    pub conservation_check: ConservationCheck,
+    pub histogram_bins: usize,
+    pub histogram_window: usize,
+    // The name of a readout, with the scale and unit to show it in
+    pub readout_units: Vec<(String, f64, String)>,
+    pub log: Option<String>,
+    pub log_interval: f64,
+    // Observable names, or None for TimeSeriesLog::default_observables
+    pub log_observables: Option<Vec<String>>,
+    pub record: Option<String>,
+    pub record_frames: bool,
+    pub play: Option<String>,
+    pub reverse_and_return: bool,
+}
+
+
+// How long a headless run lasts
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Duration {
+    Steps(u64),
+    Time(f32),
+}
+
+
+impl Options {
+    pub fn default() -> Options {
+        Options{
+            headless: !cfg!(feature = "gui"),
+            duration: Duration::Steps(600),
+            dt: 1.0 / 60.0,
+            output: String::from("results.csv"),
+            scene: None,
+            save_scene: None,
+            seed: None,
+            conservation_check: ConservationCheck::Off,
+            histogram_bins: 30,
+            histogram_window: 60,
+            readout_units: Vec::new(),
+            log: None,
+            log_interval: 0.1,
+            log_observables: None,
+            record: None,
+            record_frames: false,
+            play: None,
+            reverse_and_return: false,
+        }
+    }
+
+
+    // Parses the arguments following the program name
+    pub fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+        let mut options = Options::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next().ok_or(format!("{} expects a value", name));
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--steps"    => options.duration = Duration::Steps(parse_value(&arg, &value(&arg)?)?),
+                "--time"     => options.duration = Duration::Time(parse_value(&arg, &value(&arg)?)?),
+                "--dt"       => options.dt = parse_value(&arg, &value(&arg)?)?,
+                "--output"   => options.output = value(&arg)?,
+                "--scene"    => options.scene = Some(value(&arg)?),
+                "--save-scene" => options.save_scene = Some(value(&arg)?),
+                "--seed"     => options.seed = Some(parse_value(&arg, &value(&arg)?)?),
+                "--check-conservation"  => options.conservation_check = ConservationCheck::Log{tolerance: parse_value(&arg, &value(&arg)?)?},
+                "--assert-conservation" => options.conservation_check = ConservationCheck::Panic{tolerance: parse_value(&arg, &value(&arg)?)?},
+                "--histogram-bins"   => options.histogram_bins = parse_value(&arg, &value(&arg)?)?,
+                "--histogram-window" => options.histogram_window = parse_value(&arg, &value(&arg)?)?,
+                "--readout-unit"     => options.readout_units.push(parse_readout_unit(&value(&arg)?)?),
+                "--log"              => options.log = Some(value(&arg)?),
+                "--log-interval"     => options.log_interval = parse_value(&arg, &value(&arg)?)?,
+                "--log-observables"  => options.log_observables = Some(parse_observables(&value(&arg)?)?),
+                "--record"           => options.record = Some(value(&arg)?),
+                "--record-frames"    => options.record_frames = true,
+                "--play"             => options.play = Some(value(&arg)?),
+                "--reverse-and-return" => {
+                    options.reverse_and_return = true;
+                    options.headless = true;
+                },
+                _            => return Err(format!("unrecognised argument {}", arg)),
+            }
+        }
+
+        if options.dt == 0.0 || !options.dt.is_finite() {
+            return Err(String::from("--dt must be finite and non-zero"));
+        }
+        if options.histogram_bins == 0 || options.histogram_window == 0 {
+            return Err(String::from("--histogram-bins and --histogram-window must be positive"));
+        }
+        if options.log_interval <= 0.0 || !options.log_interval.is_finite() {
+            return Err(String::from("--log-interval must be finite and positive"));
+        }
+        if options.play.is_some() && options.headless {
+            return Err(String::from("--play needs the viewer"));
+        }
+        if let Duration::Time(t) = options.duration {
+            if t < 0.0 || !t.is_finite() {
+                return Err(String::from("--time must be finite and non-negative"));
+            }
+        }
+
+        Ok(options)
+    }
+
+
+    pub fn usage() -> &'static str {
+        "usage: particle_sim [--scene PATH] [--seed N] [--headless] [--steps N | --time T] [--dt DT] [--output PATH] [--save-scene PATH] [--check-conservation TOL | --assert-conservation TOL] [--histogram-bins N] [--histogram-window N] [--readout-unit \"NAME=SCALE UNIT\"]... [--log PATH [--log-interval T] [--log-observables NAMES]] [--record PATH [--record-frames]] [--play PATH] [--reverse-and-return]"
+    }
+}
+
+
+fn parse_value<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("invalid value for {}: {}", name, value))
+}
+
+
+// NAME=SCALE UNIT, where the unit is optional
+fn parse_readout_unit(value: &str) -> Result<(String, f64, String), String> {
+    let invalid = || format!("invalid value for --readout-unit: {}", value);
+    let mut parts = value.splitn(2, '=');
+    let name = parts.next().map(str::trim).filter(|n| !n.is_empty()).ok_or_else(invalid)?;
+    let mut rest = parts.next().ok_or_else(invalid)?.trim().splitn(2, ' ');
+    let scale = rest.next().and_then(|s| s.parse::<f64>().ok()).filter(|s| s.is_finite()).ok_or_else(invalid)?;
+    let unit = rest.next().unwrap_or("").trim();
+    Ok((String::from(name), scale, String::from(unit)))
+}
+
+
+// A comma separated list of observable names, each of which must be known
+fn parse_observables(value: &str) -> Result<Vec<String>, String> {
+    let names: Vec<String> = value.split(',').map(|n| String::from(n.trim())).collect();
+    match names.iter().find(|n| observables::named(n).is_none()) {
+        Some(name) => Err(format!("invalid value for --log-observables: no observable called {}", name)),
+        None => Ok(names),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|a| String::from(*a)))
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options.headless, !cfg!(feature = "gui"));
+        assert_eq!(options.duration, Duration::Steps(600));
+        assert_eq!(options.dt, 1.0 / 60.0);
+        assert_eq!(options.output, "results.csv");
+        assert_eq!(options.seed, None);
+        assert_eq!(options.conservation_check, ConservationCheck::Off);
+        assert_eq!((options.histogram_bins, options.histogram_window), (30, 60));
+        assert_eq!(options.log_interval, 0.1);
+        assert!(options.log.is_none() && options.log_observables.is_none() && options.record.is_none());
+        assert!(!options.reverse_and_return);
+    }
+
+
+    #[test]
+    fn flags() {
+        let options = parse(&["--headless", "--time", "2.5", "--dt", "-0.01", "--seed", "42", "--output", "out.csv",
+                              "--scene", "in.ron", "--assert-conservation", "1e-6", "--readout-unit", "mean free time=1000 ms",
+                              "--log", "log.csv", "--log-interval", "0.5", "--log-observables", "energy, temperature",
+                              "--record", "run.traj", "--record-frames"]).unwrap();
+        assert!(options.headless);
+        assert_eq!(options.duration, Duration::Time(2.5));
+        assert_eq!(options.dt, -0.01);
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.output, "out.csv");
+        assert_eq!(options.scene.as_deref(), Some("in.ron"));
+        assert_eq!(options.conservation_check, ConservationCheck::Panic{tolerance: 1e-6});
+        assert_eq!(options.readout_units, vec![(String::from("mean free time"), 1000.0, String::from("ms"))]);
+        assert_eq!(options.log.as_deref(), Some("log.csv"));
+        assert_eq!(options.log_interval, 0.5);
+        assert_eq!(options.log_observables, Some(vec![String::from("energy"), String::from("temperature")]));
+        assert_eq!(options.record.as_deref(), Some("run.traj"));
+        assert!(options.record_frames);
+
+        // Reversing a run is always headless
+        let options = parse(&["--reverse-and-return", "--steps", "10"]).unwrap();
+        assert!(options.reverse_and_return && options.headless);
+        assert_eq!(options.duration, Duration::Steps(10));
+    }
+
+
+    #[test]
+    fn bad_values_are_errors() {
+        let error = |args: &[&str]| parse(args).err().unwrap_or_else(|| panic!("{:?} parsed", args));
+        assert_eq!(error(&["--steps", "ten"]), "invalid value for --steps: ten");
+        assert_eq!(error(&["--seed"]), "--seed expects a value");
+        assert_eq!(error(&["--speed", "3"]), "unrecognised argument --speed");
+        assert_eq!(error(&["--dt", "0"]), "--dt must be finite and non-zero");
+        assert_eq!(error(&["--time", "-1"]), "--time must be finite and non-negative");
+        assert_eq!(error(&["--histogram-bins", "0"]), "--histogram-bins and --histogram-window must be positive");
+        assert_eq!(error(&["--log-interval", "inf"]), "--log-interval must be finite and positive");
+        assert_eq!(error(&["--readout-unit", "energy"]), "invalid value for --readout-unit: energy");
+        assert_eq!(error(&["--log-observables", "energy,colour"]),
+                   "invalid value for --log-observables: no observable called colour");
+        assert_eq!(error(&["--headless", "--play", "run.traj"]), "--play needs the viewer");
+    }
+}
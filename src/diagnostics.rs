@@ -0,0 +1,59 @@
+// Checks on what World::update conserves. With elastic collisions, total energy is conserved
+// exactly (up to rounding), and so is momentum once the impulses from walls, obstacles and fields
+// are taken into account. Drift is measured from a baseline taken at the first update, and energy
+// put in or taken out by moving and thermal walls is not counted as drift.
+use serde::{Serialize, Deserialize};
+use super::vector_math::Vector2;
+
+
+// The totals drift is measured from
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Baseline {
+    pub time: f64,
+    // The total energy less what the walls had put in by then
+    pub energy: f64,
+    // Kinetic energy plus the size of the potential energy, which the energy drift is relative to
+    pub energy_scale: f64,
+    // The total momentum less the impulse from walls, obstacles and the field by then
+    pub momentum: Vector2<f64>,
+    // The sum of the magnitudes of the balls' momenta, which the momentum drift is relative to, as
+    // the total is often zero
+    pub momentum_scale: f64,
+}
+
+
+// How far the totals have moved from the baseline, relative to it
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Drift {
+    pub energy: f64,
+    pub momentum: f64,
+}
+
+
+// What World::update does when the drift exceeds the tolerance. Inelastic collisions and friction
+// lose energy, so only use this for worlds where energy should be conserved.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum ConservationCheck {
+    #[default]
+    Off,
+    // Print a warning to stderr when the drift first exceeds the tolerance
+    Log { tolerance: f64 },
+    Panic { tolerance: f64 },
+}
+
+
+impl Drift {
+    pub fn largest(&self) -> f64 {
+        self.energy.abs().max(self.momentum.abs())
+    }
+}
+
+
+impl ConservationCheck {
+    pub fn tolerance(&self) -> Option<f64> {
+        match *self {
+            ConservationCheck::Off => None,
+            ConservationCheck::Log{tolerance} | ConservationCheck::Panic{tolerance} => Some(tolerance),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::mpsc::Sender;
+use super::world::CollisionKind;
+use super::ball::Ball;
+use super::vector_math::Vector2f;
+
+
+// A collision that World::update has just resolved, as passed to subscribers
+#[derive(Copy, Clone, Debug)]
+pub struct CollisionEvent {
+    pub kind: CollisionKind,
+    pub ball_index: usize,
+    // Depending on kind, the index of the other ball, of the wall, or of the obstacle, as in
+    // World::get_balls, World::get_walls and World::get_obstacles
+    pub collider_index: usize,
+    // Simulated time at which the collision happened, as given by World::get_time
+    pub time: f64,
+    // The impulse the ball received, the change in its momentum. A colliding ball received the opposite.
+    pub impulse: Vector2f,
+    // The ball's velocities just before and just after the collision
+    pub before: Velocities,
+    pub after: Velocities,
+    // The other ball's, for a collision between balls
+    pub collider_before: Option<Velocities>,
+    pub collider_after: Option<Velocities>,
+}
+
+
+#[derive(Copy, Clone, Debug, PartialEq, Default)]
+pub struct Velocities {
+    pub velocity: Vector2f,
+    pub angular_velocity: f32,
+}
+
+
+// Identifies a subscription so that it can be cancelled
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SubscriptionId(u64);
+
+
+pub type CollisionCallback = Box<dyn FnMut(&CollisionEvent)>;
+
+
+// The callbacks registered with World::subscribe
+pub struct Subscribers {
+    next_id: u64,
+    callbacks: Vec<(SubscriptionId, CollisionCallback)>,
+}
+
+
+impl Velocities {
+    pub fn of(ball: &Ball) -> Velocities {
+        Velocities{velocity: ball.velocity, angular_velocity: ball.angular_velocity}
+    }
+}
+
+
+impl Default for Subscribers {
+    fn default() -> Subscribers {
+        Subscribers::new()
+    }
+}
+
+
+impl Subscribers {
+    pub fn new() -> Subscribers {
+        Subscribers{next_id: 0, callbacks: Vec::new()}
+    }
+
+
+    pub fn add(&mut self, callback: CollisionCallback) -> SubscriptionId {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.callbacks.push((id, callback));
+        id
+    }
+
+
+    // Returns false if there was no such subscription
+    pub fn remove(&mut self, id: SubscriptionId) -> bool {
+        let len = self.callbacks.len();
+        self.callbacks.retain(|(i, _)| *i != id);
+        self.callbacks.len() != len
+    }
+
+
+    // Sends every event down sender, for handling on another thread or later on. Sending stops
+    // silently once the receiver is dropped, though the subscription remains until removed.
+    pub fn add_channel(&mut self, sender: Sender<CollisionEvent>) -> SubscriptionId {
+        self.add(Box::new(move |event: &CollisionEvent| {
+            let _ = sender.send(*event);
+        }))
+    }
+
+
+    pub fn notify(&mut self, event: &CollisionEvent) {
+        for (_, callback) in &mut self.callbacks {
+            callback(event);
+        }
+    }
+
+
+    pub fn is_empty(&self) -> bool {
+        self.callbacks.is_empty()
+    }
+}
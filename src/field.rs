@@ -0,0 +1,33 @@
+// External fields acting on every ball between collisions. Each gives a ball a constant
+// acceleration, so its free flight is a parabola and collision times can still be solved for exactly.
+use serde::{Serialize, Deserialize};
+use super::ball::Ball;
+use super::vector_math::{Vector2f, dot_product};
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum Field {
+    #[default]
+    None,
+    // The same acceleration for every ball, as for gravity near the ground
+    Gravity(Vector2f),
+    // The same force on every ball, so that lighter balls accelerate more
+    Force(Vector2f),
+}
+
+
+impl Field {
+    pub fn acceleration(&self, ball: &Ball) -> Vector2f {
+        match *self {
+            Field::None => Vector2f::default(),
+            Field::Gravity(g) => g,
+            Field::Force(f) => f / ball.get_mass(),
+        }
+    }
+
+
+    // Relative to a ball at the origin
+    pub fn potential_energy(&self, ball: &Ball) -> f32 {
+        -ball.get_mass() * dot_product(&self.acceleration(ball), &ball.get_position())
+    }
+}
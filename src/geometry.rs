@@ -1,6 +1,7 @@
 #![allow(dead_code)]
-use sfml::system::Vector2f;
 use super::vector_math;
+use super::vector_math::Vector2f;
+use serde::{Serialize, Deserialize};
 
 #[derive(Debug, Copy, Clone)]
 pub struct Circle {
@@ -13,4 +14,14 @@ impl Circle {
     pub fn intersect(&self, b: &Circle) -> bool {
         vector_math::length_squared(&(self.position - b.position)) < (self.radius + b.radius).powf(2.0)
     }
-}
\ No newline at end of file
+}
+
+
+// An axis-aligned rectangle
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Rect {
+    pub left:   f32,
+    pub top:    f32,
+    pub width:  f32,
+    pub height: f32,
+}
@@ -0,0 +1,114 @@
+// Runs the simulation without a window and writes the final state of every ball to disk, finishing
+// the log of observables and the trajectory if they are being recorded. Or, for --reverse-and-return,
+// runs it forwards and back again and writes how far each ball is from where it started.
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use super::cli::{Options, Duration};
+use particle_sim::{World, Scene, Reversal, reverse_and_return};
+
+
+pub fn run(options: &Options, mut world: World) -> Result<(), Box<dyn std::error::Error>> {
+
+    // Whole steps of dt, then for a run of fixed duration a shortened final step
+    // so that it ends exactly at the requested time
+    let (steps, remainder) = match options.duration {
+        Duration::Steps(n) => (n, 0.0),
+        Duration::Time(t) => {
+            // The tolerance stops rounding error in dt adding a vanishingly short extra step
+            let steps = (t as f64 / options.dt.abs() as f64 + 1e-4).floor();
+            let remainder = (t as f64 - steps * options.dt.abs() as f64).max(0.0) as f32;
+            (steps as u64, remainder)
+        }
+    };
+
+    let mut dts = vec![options.dt; steps as usize];
+    if remainder > 0.0 {
+        dts.push(remainder * options.dt.signum());
+    }
+    let elapsed = steps as f64 * options.dt.abs() as f64 + remainder as f64;
+
+    if options.reverse_and_return {
+        let reversal = reverse_and_return(&mut world, &dts);
+        write_reversal(&reversal, &world, &options.output)?;
+        println!("ran {} s forwards and back with seed {}: {} of {} balls returned exactly, largest distance {:e} \
+                  (rms {:e}), largest velocity error {:e}, wrote {}",
+                 elapsed as f32, world.get_seed().map_or(String::from("unknown"), |s| s.to_string()),
+                 reversal.displacements.len() - reversal.count_displaced(0.0), reversal.displacements.len(),
+                 reversal.max_displacement(), reversal.rms_displacement(), reversal.max_velocity_error(), options.output);
+        return Ok(());
+    }
+
+    for &dt in &dts {
+        world.update(dt);
+    }
+
+    if let Some(log) = world.take_log() {
+        log.finish()?;
+    }
+    if let Some(recorder) = world.take_recorder() {
+        recorder.finish()?;
+    }
+    write_state(&world, &options.output)?;
+    if let Some(path) = &options.save_scene {
+        Scene::from_world(&world).save(path)?;
+    }
+    println!("ran {} steps ({} s simulated) with seed {}, wrote {}", dts.len(), elapsed as f32,
+             world.get_seed().map_or(String::from("unknown"), |s| s.to_string()), options.output);
+    Ok(())
+}
+
+
+// One row per ball, as CSV, after a comment line recording the seed
+pub fn write_state(world: &World, path: &str) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    match world.get_seed() {
+        Some(seed) => writeln!(out, "# seed: {}", seed)?,
+        None       => writeln!(out, "# seed: unknown")?,
+    }
+    writeln!(out, "index,x,y,vx,vy,orientation,omega,radius,mass")?;
+    for (i, ball) in world.get_balls().iter().enumerate() {
+        let p = ball.get_position();
+        writeln!(out, "{},{},{},{},{},{},{},{},{}", i, p.x, p.y, ball.velocity.x, ball.velocity.y,
+                 ball.orientation, ball.angular_velocity, ball.circle.radius, ball.get_mass())?;
+    }
+    out.flush()
+}
+
+
+// One row per ball, as CSV, after a comment line recording the seed
+fn write_reversal(reversal: &Reversal, world: &World, path: &str) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    match world.get_seed() {
+        Some(seed) => writeln!(out, "# seed: {}", seed)?,
+        None       => writeln!(out, "# seed: unknown")?,
+    }
+    writeln!(out, "index,displacement,velocity_error")?;
+    for (i, (d, v)) in reversal.displacements.iter().zip(&reversal.velocity_errors).enumerate() {
+        writeln!(out, "{},{},{}", i, d, v)?;
+    }
+    out.flush()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_writes_every_ball() {
+        let path = std::env::temp_dir().join(format!("headless_run_{}.csv", std::process::id()));
+        let options = Options{headless: true, duration: Duration::Time(0.25), output: String::from(path.to_str().unwrap()),
+                              ..Options::default()};
+        let world = World::new(3);
+        let count = world.get_balls().len();
+        run(&options, world).unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), count + 2);
+        assert_eq!(lines[0], "# seed: 3");
+        assert_eq!(lines[1], "index,x,y,vx,vy,orientation,omega,radius,mass");
+        assert!(lines[2..].iter().enumerate().all(|(i, line)| line.starts_with(&format!("{},", i)) && line.split(',').count() == 9));
+    }
+}
@@ -1,26 +1,27 @@
-use sfml::graphics::{RenderWindow, RenderTarget, Text, Font, Transformable};
-use super::world::World;
+use sfml::graphics::{RenderWindow, RenderTarget, Text, Font, Transformable, RectangleShape, Shape, Color,
+                      VertexArray, Vertex, PrimitiveType};
+use particle_sim::{World, SpeedHistogram, Readout};
 use sfml::system::Vector2f;
 use super::slider::Slider;
 
 pub struct Interface {
     font: Font,
     time_slider: Slider,
+    histogram: SpeedHistogram,
+    readouts: Vec<Readout>,
 }
 
 
 impl Interface {
-    pub fn vec_to_string(v: &Vector2f) -> String {
-        format!("({}, {})", v.x, v.y)
-    }
-
-
-    pub fn new() -> Interface {
+    pub fn new(histogram: SpeedHistogram, readouts: Vec<Readout>) -> Interface {
+        let slider_y = 20.0 + 17.0 * readouts.len() as f32;
         Interface{
+            histogram,
+            readouts,
             font: Font::from_file("data/Ubuntu-R.ttf").expect("failed to load font"),
             time_slider: Slider::new(
                 Vector2f{x: 200.0, y: 5.0},
-                Vector2f{x: 0.0, y: 90.0},
+                Vector2f{x: 0.0, y: slider_y},
                 Vector2f{x: 20.0, y: 10.0},
                 0.5,
                 -1.0,
@@ -32,16 +33,40 @@ impl Interface {
 
 
     pub fn draw(&self, window: &mut RenderWindow, world: &World) {
-        let mut t = Text::new(&format!("v1: {}", Interface::vec_to_string(&world.get_balls()[0].velocity)),
-                              &self.font, 32);
-        window.draw(&t);
+        let mut t = Text::new("", &self.font, 14);
+        for (i, readout) in self.readouts.iter().enumerate() {
+            t.set_string(&readout.text(world));
+            t.set_position(Vector2f{x: 5.0, y: 5.0 + 17.0 * i as f32});
+            window.draw(&t);
+        }
 
-        t.set_string(&format!("v2: {}", Interface::vec_to_string(&world.get_balls()[1].velocity)));
-        t.set_position(Vector2f{x: 0.0, y: 40.0});
+        let seed = world.get_seed().map_or(String::from("unknown"), |s| s.to_string());
+        t.set_string(&format!("seed: {}", seed));
+        t.set_character_size(16);
+        t.set_position(Vector2f{x: 600.0, y: 5.0});
         window.draw(&t);
 
+        let momentum = world.momentum();
+        t.set_string(&format!("energy: {:.1}  momentum: ({:.1}, {:.1})", world.total_energy(), momentum.x, momentum.y));
+        t.set_position(Vector2f{x: 600.0, y: 25.0});
+        window.draw(&t);
+        if let Some(drift) = world.drift() {
+            t.set_string(&format!("drift: energy {:.2e}  momentum {:.2e}", drift.energy, drift.momentum));
+            t.set_position(Vector2f{x: 600.0, y: 45.0});
+            window.draw(&t);
+        }
+        if let Some(pressure) = world.pressure() {
+            t.set_string(&match world.predicted_pressure() {
+                Some(predicted) => format!("pressure: {:.1}  ideal gas: {:.1}  hard disks: {:.1}",
+                                           pressure, predicted.ideal_gas, predicted.hard_disks),
+                None => format!("pressure: {:.1}", pressure),
+            });
+            t.set_position(Vector2f{x: 600.0, y: 65.0});
+            window.draw(&t);
+        }
 
         self.time_slider.draw(window);
+        self.draw_histogram(window);
 
         // let invert_time = self.get_time_factor() < 0.0;
 
@@ -57,6 +82,57 @@ impl Interface {
     }
 
 
+    // Takes in the state of the world after each update, for the panels that follow it over time
+    pub fn observe(&mut self, world: &World) {
+        self.histogram.record(world.get_balls());
+        for readout in &mut self.readouts {
+            readout.observable.observe(world);
+        }
+    }
+
+
+    // The speed histogram, with the Maxwell-Boltzmann distribution at the measured temperature over it
+    fn draw_histogram(&self, window: &mut RenderWindow) {
+        let origin = Vector2f{x: 250.0, y: 10.0};
+        let size = Vector2f{x: 320.0, y: 130.0};
+        let mut background = RectangleShape::with_size(size);
+        background.set_position(origin);
+        background.set_fill_color(&Color::rgba(0, 0, 0, 60));
+        window.draw(&background);
+
+        let densities = self.histogram.densities();
+        let max_speed = self.histogram.max_speed();
+        let samples = 100;
+        let curve: Vec<f64> = (0..=samples)
+            .map(|i| self.histogram.expected_density(max_speed * i as f64 / samples as f64))
+            .collect();
+        let peak = densities.iter().chain(&curve).copied().fold(0.0, f64::max);
+        if peak <= 0.0 {
+            return;
+        }
+        let height = |density: f64| (density / peak) as f32 * (size.y - 20.0);
+
+        let bar_width = size.x / densities.len() as f32;
+        for (i, &density) in densities.iter().enumerate() {
+            let mut bar = RectangleShape::with_size(Vector2f{x: (bar_width - 1.0).max(1.0), y: height(density)});
+            bar.set_position(Vector2f{x: origin.x + i as f32 * bar_width, y: origin.y + size.y - height(density)});
+            bar.set_fill_color(&Color::rgb(240, 240, 240));
+            window.draw(&bar);
+        }
+
+        let mut line = VertexArray::new(PrimitiveType::LineStrip, 0);
+        for (i, &density) in curve.iter().enumerate() {
+            let position = Vector2f{x: origin.x + size.x * i as f32 / samples as f32, y: origin.y + size.y - height(density)};
+            line.append(&Vertex::with_pos_color(position, Color::RED));
+        }
+        window.draw(&line);
+
+        let mut label = Text::new(&format!("speeds, T = {:.0}", self.histogram.temperature()), &self.font, 14);
+        label.set_position(Vector2f{x: origin.x + 5.0, y: origin.y + 2.0});
+        window.draw(&label);
+    }
+
+
     pub fn get_time_factor(&self) -> f32 {
         self.time_slider.get_value()
     }
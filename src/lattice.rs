@@ -0,0 +1,125 @@
+// The state of Integrator::Exact. Each ball's position is kept as a whole number of lattice spacings,
+// along with how far it moved over the last step. A step adds the acceleration, rounded to the lattice,
+// to the move and the move to the position, all in integers, so every step can be undone exactly.
+// The balls are given the positions, and velocities of the last move over the step, after each step.
+use super::ball::Ball;
+use super::periodic::Periodic;
+use super::vector_math::Vector2f;
+
+
+pub struct Lattice {
+    step: f64,
+    resolution: f64,
+    positions: Vec<[i64; 2]>,
+    moves: Vec<[i64; 2]>,
+    // The start and length of the cell on each periodic axis, in lattice spacings
+    cell: [Option<(i64, i64)>; 2],
+    // The positions and velocities last given to the balls, to tell when they have been changed
+    written: Vec<(Vector2f, Vector2f)>,
+}
+
+
+impl Lattice {
+    // The balls as they are, rounded to the nearest lattice points and moves
+    pub fn new(balls: &[Ball], step: f64, resolution: f64, periodic: &Periodic) -> Lattice {
+        let round = |x: f32, scale: f64| (x as f64 * scale).round() as i64;
+        let positions = balls.iter()
+            .map(|b| [round(b.get_position().x, 1.0 / resolution), round(b.get_position().y, 1.0 / resolution)])
+            .collect();
+        let moves = balls.iter()
+            .map(|b| [round(b.velocity.x, step / resolution), round(b.velocity.y, step / resolution)])
+            .collect();
+        let axis = |periodic: bool, start: f32, length: f32| {
+            if periodic { Some((round(start, 1.0 / resolution), round(length, 1.0 / resolution))) } else { None }
+        };
+        let cell = [axis(periodic.x, periodic.cell.left, periodic.cell.width),
+                    axis(periodic.y, periodic.cell.top, periodic.cell.height)];
+        let mut lattice = Lattice{step, resolution, positions, moves, cell, written: Vec::new()};
+        lattice.wrap();
+        lattice
+    }
+
+
+    // Whether the lattice still describes the balls, which have not been changed since it last wrote
+    // to them, for the same step and resolution
+    pub fn matches(&self, balls: &[Ball], step: f64, resolution: f64) -> bool {
+        self.step == step && self.resolution == resolution && self.written.len() == balls.len()
+            && self.written.iter().zip(balls).all(|(w, b)| w.0 == b.get_position() && w.1 == b.velocity)
+    }
+
+
+    // One step on, given the accelerations at the current positions
+    pub fn forward(&mut self, accelerations: &[Vector2f]) {
+        for (m, a) in self.moves.iter_mut().zip(accelerations) {
+            let kick = kick(*a, self.step, self.resolution);
+            m[0] += kick[0];
+            m[1] += kick[1];
+        }
+        self.shift(1);
+    }
+
+
+    // The first half of a step back: the positions before the last move
+    pub fn retreat_positions(&mut self) {
+        self.shift(-1);
+    }
+
+
+    // The second half of a step back, given the accelerations at the positions retreated to
+    pub fn retreat_moves(&mut self, accelerations: &[Vector2f]) {
+        for (m, a) in self.moves.iter_mut().zip(accelerations) {
+            let kick = kick(*a, self.step, self.resolution);
+            m[0] -= kick[0];
+            m[1] -= kick[1];
+        }
+    }
+
+
+    // Turns the motion around, given the accelerations at the current positions, so that stepping
+    // forwards retraces the steps taken so far
+    pub fn reverse(&mut self, accelerations: &[Vector2f]) {
+        for (m, a) in self.moves.iter_mut().zip(accelerations) {
+            let kick = kick(*a, self.step, self.resolution);
+            *m = [-m[0] - kick[0], -m[1] - kick[1]];
+        }
+    }
+
+
+    pub fn write(&mut self, balls: &mut [Ball]) {
+        let speed = self.resolution / self.step;
+        self.written.clear();
+        for ((ball, p), m) in balls.iter_mut().zip(&self.positions).zip(&self.moves) {
+            ball.circle.position = Vector2f{x: (p[0] as f64 * self.resolution) as f32, y: (p[1] as f64 * self.resolution) as f32};
+            ball.velocity = Vector2f{x: (m[0] as f64 * speed) as f32, y: (m[1] as f64 * speed) as f32};
+            self.written.push((ball.circle.position, ball.velocity));
+        }
+    }
+
+
+    // Moves every ball by its move times sign
+    fn shift(&mut self, sign: i64) {
+        for (p, m) in self.positions.iter_mut().zip(&self.moves) {
+            p[0] += sign * m[0];
+            p[1] += sign * m[1];
+        }
+        self.wrap();
+    }
+
+
+    fn wrap(&mut self) {
+        for p in &mut self.positions {
+            for (axis, cell) in self.cell.iter().enumerate() {
+                if let Some((start, length)) = *cell {
+                    p[axis] = start + (p[axis] - start).rem_euclid(length);
+                }
+            }
+        }
+    }
+}
+
+
+// The change in a move from acceleration over a step, in lattice spacings
+fn kick(acceleration: Vector2f, step: f64, resolution: f64) -> [i64; 2] {
+    let scale = step * step / resolution;
+    [(acceleration.x as f64 * scale).round() as i64, (acceleration.y as f64 * scale).round() as i64]
+}
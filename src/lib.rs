@@ -0,0 +1,58 @@
+// Event-driven simulation of hard disks bouncing around a box.
+//
+// A World holds balls and walls. Step it with World::update, read its state back with the get_*
+// methods, and use World::subscribe or World::subscribe_channel to hear about each collision as it is
+// resolved. Worlds can also be loaded from and saved to scene files, see Scene. With the gui feature,
+// World::draw renders it with SFML.
+#[cfg(feature = "gui")]
+extern crate sfml;
+extern crate num;
+extern crate rand;
+
+pub mod ball;
+pub mod world;
+pub mod geometry;
+pub mod vector_math;
+pub mod math;
+pub mod plane;
+pub mod wall;
+pub mod obstacle;
+pub mod broad_phase;
+pub mod events;
+pub mod scene;
+pub mod restitution;
+pub mod field;
+pub mod potential;
+pub mod periodic;
+pub mod diagnostics;
+pub mod statistics;
+pub mod pressure;
+pub mod observables;
+pub mod time_series;
+pub mod trajectory;
+pub mod reversal;
+mod scheduler;
+mod lattice;
+#[cfg(feature = "gui")]
+mod render;
+
+pub use ball::Ball;
+pub use world::{World, CollisionKind};
+pub use geometry::{Circle, Rect};
+pub use plane::{Plane, Motion};
+pub use wall::{Wall, Segment, CircularArc};
+pub use obstacle::Obstacle;
+pub use vector_math::Vector2f;
+pub use broad_phase::BroadPhase;
+pub use events::{CollisionEvent, Velocities, SubscriptionId};
+pub use scene::{Scene, SceneError};
+pub use restitution::{CombineRule, CollapseGuard};
+pub use field::Field;
+pub use potential::{Integrator, Potential};
+pub use periodic::Periodic;
+pub use diagnostics::{Drift, ConservationCheck};
+pub use statistics::{SpeedHistogram, EquationOfState};
+pub use observables::{Observable, Readout};
+pub use time_series::TimeSeriesLog;
+pub use trajectory::{TrajectoryRecorder, TrajectoryMode, Trajectory, Playback, TrajectoryError};
+pub use reversal::{reverse_and_return, Reversal};
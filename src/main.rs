@@ -1,23 +1,124 @@
+#[cfg(feature = "gui")]
 extern crate sfml;
 extern crate num;
-extern crate rand;
+extern crate particle_sim;
 
-mod ball;
-mod world;
-mod geometry;
-mod vector_math;
+#[cfg(feature = "gui")]
 mod interface;
+#[cfg(feature = "gui")]
 mod slider;
-mod math;
-mod plane;
+mod cli;
+mod headless;
 
+#[cfg(feature = "gui")]
 use sfml::window::{ContextSettings, Event, Style};
+#[cfg(feature = "gui")]
 use sfml::graphics::{RenderWindow, RenderTarget, Color};
-use world::World;
+use particle_sim::World;
+#[cfg(feature = "gui")]
 use interface::Interface;
+use cli::Options;
+use particle_sim::{Scene, TimeSeriesLog, TrajectoryRecorder, TrajectoryMode, observables};
+#[cfg(feature = "gui")]
+use particle_sim::{SpeedHistogram, Readout, Trajectory, Playback};
+
+
+// How often a recorded trajectory has a snapshot of every ball, in simulated time
+const KEYFRAME_INTERVAL: f64 = 1.0;
+
+
+// What the viewer shows
+#[cfg(feature = "gui")]
+enum Shown {
+    Simulation(Box<World>),
+    // A recorded trajectory, which the time slider scrubs through
+    Playback(Box<Playback>),
+}
 
 
 fn main() {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n{}", message, Options::usage());
+            std::process::exit(2);
+        }
+    };
+
+    #[cfg(feature = "gui")]
+    {
+        if let Some(path) = &options.play {
+            match Trajectory::load(path).and_then(Playback::new) {
+                Ok(playback) => run_viewer(Shown::Playback(Box::new(playback)), &options),
+                Err(e) => {
+                    eprintln!("{}: {}", path, e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+    }
+
+    let (mut world, scene) = match &options.scene {
+        None => (World::new(options.seed.unwrap_or_else(World::random_seed)), None),
+        Some(path) => match Scene::load(path).and_then(|mut scene| {
+            scene.seed = options.seed.or(scene.seed);
+            scene.build().map(|world| (world, Some(scene)))
+        }) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    };
+    world.set_conservation_check(options.conservation_check);
+    if let Some(path) = &options.log {
+        let scene = scene.unwrap_or_else(|| Scene::from_world(&world));
+        match open_log(path, &options, &world, &scene) {
+            Ok(log) => { world.set_log(log); },
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &options.record {
+        let mode = if options.record_frames { TrajectoryMode::Frames } else { TrajectoryMode::Events };
+        match TrajectoryRecorder::create(path, mode, KEYFRAME_INTERVAL, &world) {
+            Ok(recorder) => { world.set_recorder(recorder); },
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if options.headless {
+        if let Err(e) = headless::run(&options, world) {
+            eprintln!("headless run failed: {}", e);
+            std::process::exit(1);
+        }
+    } else {
+        #[cfg(feature = "gui")]
+        run_viewer(Shown::Simulation(Box::new(world)), &options);
+    }
+}
+
+
+// The log for --log, recording scene in its header
+fn open_log(path: &str, options: &Options, world: &World, scene: &Scene) -> std::io::Result<TimeSeriesLog> {
+    let observables = match &options.log_observables {
+        Some(names) => names.iter().filter_map(|n| observables::named(n)).collect(),
+        None => TimeSeriesLog::default_observables(),
+    };
+    let out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    TimeSeriesLog::new(Box::new(out), observables, options.log_interval, world, scene)
+}
+
+
+#[cfg(feature = "gui")]
+fn run_viewer(mut shown: Shown, options: &Options) {
         // Create the window of the application
     let mut window = RenderWindow::new((1000, 1000),
                                              "SFML Example", Style::CLOSE,
@@ -26,8 +127,14 @@ fn main() {
     let dt = 1. / 60.;
     window.set_framerate_limit(60);
 
-    let mut world = World::new();
-    let mut interface = Interface::new();
+    let mut readouts = Readout::default_panel();
+    for (name, scale, unit) in &options.readout_units {
+        if let Err(message) = Readout::set_unit(&mut readouts, name, *scale, unit) {
+            eprintln!("--readout-unit: {}", message);
+            std::process::exit(2);
+        }
+    }
+    let mut interface = Interface::new(SpeedHistogram::new(options.histogram_bins, options.histogram_window), readouts);
 
     while window.is_open() {
         // Handle events
@@ -43,12 +150,45 @@ fn main() {
 
         // Clear the window
         window.clear(&Color::rgb(0, 200, 200));
-        world.draw(&mut window);
-        interface.draw(&mut window, &world);
+        shown.world().draw(&mut window);
+        interface.draw(&mut window, shown.world());
 
         // Display things on screen
         window.display();
 
-        world.update(dt * interface.get_time_factor());
+        shown.step(dt * interface.get_time_factor());
+        interface.observe(shown.world());
+    }
+
+    if let Shown::Simulation(world) = &mut shown {
+        if let Some(log) = world.take_log() {
+            if let Err(e) = log.finish() {
+                eprintln!("writing the log failed: {}", e);
+            }
+        }
+        if let Some(recorder) = world.take_recorder() {
+            if let Err(e) = recorder.finish() {
+                eprintln!("recording the trajectory failed: {}", e);
+            }
+        }
+    }
+}
+
+
+#[cfg(feature = "gui")]
+impl Shown {
+    fn world(&self) -> &World {
+        match self {
+            Shown::Simulation(world) => world,
+            Shown::Playback(playback) => playback.get_world(),
+        }
+    }
+
+
+    fn step(&mut self, dt: f32) {
+        match self {
+            Shown::Simulation(world) => world.update(dt),
+            Shown::Playback(playback) => playback.seek(playback.get_time() + dt as f64),
+        }
     }
 }
@@ -1,21 +1,218 @@
 use num::{Float};
-use sfml::system::Vector2;
+use rand::Rng;
+use super::vector_math::Vector2;
 
 // Outputs the roots to a degree 2 polynomial,
 // specified in terms of the coefficients: ax^2 + bx + c
-// Returns None if there are no real roots
+// Returns None if there are no real roots. The roots are in ascending order; when a is zero the
+// single root of bx + c is given twice.
 pub fn find_roots<T: Float + Copy>(a: T, b: T, c: T) -> Option<(T, T)> {
-    let b_sq = b * b;
-    let four_a_c = T::from(4).unwrap() * a * c;
-    if four_a_c > b_sq {
+    let zero = T::zero();
+    if a == zero {
+        // A constant has either no roots or every x as one
+        if b == zero {
+            return None;
+        }
+        let root = -c / b;
+        return Some((root, root));
+    }
+    let discriminant = b * b - T::from(4).unwrap() * a * c;
+    if discriminant < zero || discriminant.is_nan() {
         return None;    // No real roots
     }
-    let sqrt_term = (b_sq - four_a_c).sqrt();
-    let two_a = T::from(2).unwrap() * a;
-    Some( ((-b + sqrt_term)/two_a, (-b - sqrt_term)/two_a) )
+    // The textbook formula subtracts nearly equal numbers for the smaller root when b^2 is much larger
+    // than 4ac. Giving the square root the sign of b avoids that, and the other root follows from the
+    // product of the roots being c/a.
+    let q = -(b + discriminant.sqrt() * b.signum()) / T::from(2).unwrap();
+    if q == zero {
+        // Only when b and c are both zero
+        return Some((zero, zero));
+    }
+    let (r1, r2) = (q / a, c / q);
+    Some(if r1 <= r2 {(r1, r2)} else {(r2, r1)})
 }
 
 
 pub fn convert_vector2<T1: Copy, T2: Copy + From<T1>>(a: Vector2<T1>) -> Vector2<T2> {
     Vector2::<T2>{x: T2::from(a.x), y: T2::from(a.y)}
 }
+
+
+// The real roots of a polynomial, in ascending order, given its coefficients from the highest power
+// down. Roots of even multiplicity, where the polynomial touches zero without crossing, are only
+// found if it is exactly zero there.
+pub fn polynomial_roots(coefficients: &[f64]) -> Vec<f64> {
+    let start = coefficients.iter().position(|c| *c != 0.0).unwrap_or(coefficients.len());
+    let p = &coefficients[start..];
+    match p.len() {
+        0 | 1 => return Vec::new(),
+        2 => return vec![-p[1] / p[0]],
+        3 => {
+            return match find_roots(p[0], p[1], p[2]) {
+                Some((r1, r2)) => vec![r1, r2],
+                None => Vec::new(),
+            };
+        },
+        _ => (),
+    }
+
+    // Between consecutive roots of the derivative the polynomial is monotonic, so it has at most
+    // one root there, which can be found by bisection. Every root lies within the Cauchy bound.
+    let degree = p.len() - 1;
+    let derivative: Vec<f64> = p[..degree].iter().enumerate()
+        .map(|(i, c)| c * (degree - i) as f64)
+        .collect();
+    let bound = 1.0 + p[1..].iter().map(|c| (c / p[0]).abs()).fold(0.0, f64::max);
+
+    let mut points = vec![-bound];
+    points.extend(polynomial_roots(&derivative).into_iter().filter(|x| x.abs() < bound));
+    points.push(bound);
+
+    let mut roots: Vec<f64> = Vec::new();
+    for interval in points.windows(2) {
+        if let Some(root) = bisect(p, &derivative, interval[0], interval[1]) {
+            if roots.last() != Some(&root) {
+                roots.push(root);
+            }
+        }
+    }
+    roots
+}
+
+
+// Evaluates a polynomial, given its coefficients from the highest power down
+pub fn evaluate(coefficients: &[f64], x: f64) -> f64 {
+    coefficients.iter().fold(0.0, |sum, c| sum * x + c)
+}
+
+
+// How closely polynomial_roots pins down each root, relative to its size and absolutely
+const ROOT_TOLERANCE: f64 = 1e-12;
+
+
+// The root of p between lo and hi, if p changes sign there. Newton's method, falling back on
+// bisection whenever a step would leave the bracket around the root.
+fn bisect(p: &[f64], derivative: &[f64], mut lo: f64, mut hi: f64) -> Option<f64> {
+    let mut p_lo = evaluate(p, lo);
+    let p_hi = evaluate(p, hi);
+    if p_lo == 0.0 {
+        return Some(lo);
+    }
+    if p_hi == 0.0 {
+        return Some(hi);
+    }
+    if (p_lo > 0.0) == (p_hi > 0.0) {
+        return None;
+    }
+    let mut x = 0.5 * (lo + hi);
+    loop {
+        let p_x = evaluate(p, x);
+        if p_x == 0.0 {
+            return Some(x);
+        }
+        if (p_x > 0.0) == (p_lo > 0.0) {
+            lo = x;
+            p_lo = p_x;
+        } else {
+            hi = x;
+        }
+        if hi - lo <= ROOT_TOLERANCE * (1.0 + x.abs()) {
+            return Some(0.5 * (lo + hi));
+        }
+        let newton = x - p_x / evaluate(derivative, x);
+        if newton > lo && newton < hi {
+            if (newton - x).abs() <= ROOT_TOLERANCE * (1.0 + x.abs()) {
+                return Some(newton);
+            }
+            x = newton;
+        } else {
+            x = 0.5 * (lo + hi);
+        }
+    }
+}
+
+
+// Box-Muller transform
+pub fn standard_normal<R: Rng>(rng: &mut R) -> f32 {
+    let u1: f32 = 1.0 - rng.gen::<f32>();    // in (0, 1], so the log is finite
+    let u2: f32 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+
+    // A number with a random sign and a magnitude spread evenly in log scale over many orders
+    fn random_scaled(rng: &mut StdRng) -> f64 {
+        let magnitude = 10f64.powf(rng.gen_range(-8.0, 8.0));
+        if rng.gen::<bool>() { magnitude } else { -magnitude }
+    }
+
+
+    // How far the quadratic is from zero at x, relative to the size of its terms there
+    fn relative_residual(a: f64, b: f64, c: f64, x: f64) -> f64 {
+        (a * x * x + b * x + c).abs() / (a * x * x).abs().max((b * x).abs()).max(c.abs())
+    }
+
+
+    #[test]
+    fn find_roots_are_roots() {
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..100_000 {
+            let (a, b, c) = (random_scaled(&mut rng), random_scaled(&mut rng), random_scaled(&mut rng));
+            match find_roots(a, b, c) {
+                Some((r1, r2)) => {
+                    assert!(r1 <= r2, "roots of {} {} {} out of order", a, b, c);
+                    for &r in &[r1, r2] {
+                        assert!(relative_residual(a, b, c, r) < 1e-12, "{} is not a root of {} {} {}", r, a, b, c);
+                    }
+                },
+                None => assert!(b * b < 4.0 * a * c, "missed the roots of {} {} {}", a, b, c),
+            }
+        }
+    }
+
+
+    #[test]
+    fn find_roots_from_known_roots() {
+        // Widely separated roots, which the textbook formula loses the smaller of, and double roots
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..100_000 {
+            let x1 = random_scaled(&mut rng);
+            let x2 = if rng.gen_range(0, 10) == 0 { x1 } else { random_scaled(&mut rng) };
+            let a = random_scaled(&mut rng);
+            let (b, c) = (-a * (x1 + x2), a * x1 * x2);
+            let (lower, upper) = if x1 <= x2 { (x1, x2) } else { (x2, x1) };
+            // The coefficients are themselves rounded, so the roots can only be as accurate as that. A
+            // double root may become a pair of complex ones, or real ones half the digits apart.
+            match find_roots(a, b, c) {
+                Some((r1, r2)) if x1 != x2 => {
+                    assert!((r1 - lower).abs() <= 1e-9 * lower.abs(), "{} for {}", r1, lower);
+                    assert!((r2 - upper).abs() <= 1e-9 * upper.abs(), "{} for {}", r2, upper);
+                },
+                Some((r1, r2)) => {
+                    assert!((r1 - x1).abs() <= 1e-6 * x1.abs() && (r2 - x1).abs() <= 1e-6 * x1.abs(),
+                            "{} and {} for {} twice", r1, r2, x1);
+                },
+                None => assert!(x1 == x2, "no roots for {} and {}", x1, x2),
+            }
+        }
+    }
+
+
+    #[test]
+    fn find_roots_degenerate_cases() {
+        assert_eq!(find_roots(1.0, 1e8, 1.0).map(|r| r.1), Some(-1e-8));
+        assert_eq!(find_roots(0.0, 2.0, -3.0), Some((1.5, 1.5)));
+        assert_eq!(find_roots(0.0, 0.0, 1.0), None);
+        assert_eq!(find_roots(0.0, 0.0, 0.0), None);
+        assert_eq!(find_roots(2.0, 0.0, 0.0), Some((0.0, 0.0)));
+        assert_eq!(find_roots(1.0, 0.0, 1.0), None);
+        assert_eq!(find_roots(1.0, -2.0, 1.0), Some((1.0, 1.0)));
+        assert_eq!(find_roots(f64::NAN, 1.0, 1.0), None);
+        assert_eq!(find_roots(0.0f32, 4.0, 2.0), Some((-0.5, -0.5)));
+    }
+}
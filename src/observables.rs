@@ -0,0 +1,417 @@
+// Quantities measured from a world, for on-screen readouts and logging. Each is an Observable, so
+// new ones can be added without changing the code that shows or records them. Values are in the
+// simulation's own units, with Boltzmann's constant taken as 1; a Readout scales them for display.
+use std::collections::VecDeque;
+use super::world::{World, CollisionKind};
+use super::statistics;
+use super::vector_math;
+
+
+pub trait Observable {
+    fn name(&self) -> String;
+
+
+    // The name of each value, for observables with more than one
+    fn labels(&self, _world: &World) -> Vec<String> {
+        vec![self.name()]
+    }
+
+
+    // Called after every update, for observables that follow the world over time
+    fn observe(&mut self, _world: &World) {}
+
+
+    fn values(&self, world: &World) -> Vec<f64>;
+}
+
+
+// An observable shown as its values multiplied by scale, followed by unit
+pub struct Readout {
+    pub observable: Box<dyn Observable>,
+    pub scale: f64,
+    pub unit: String,
+}
+
+
+pub struct BallCount;
+
+pub struct Temperature;
+
+// Kinetic plus potential
+pub struct TotalEnergy;
+
+// The mean kinetic energy per degree of freedom, translational and rotational separately. In
+// equilibrium, with rough balls to exchange spin, the two are equal.
+pub struct Equipartition;
+
+// Collisions between balls per unit time
+pub struct CollisionRate {
+    counter: CollisionCounter,
+}
+
+// The mean time a ball travels between collisions with anything
+pub struct MeanFreeTime {
+    counter: CollisionCounter,
+}
+
+// The mean distance a ball travels between collisions with anything
+pub struct MeanFreePath {
+    counter: CollisionCounter,
+}
+
+// The total momentum of the balls, x then y
+pub struct Momentum;
+
+// The pressure on each wall, averaged over the world's pressure window, or NaN before the first
+// update. See World::wall_pressures.
+pub struct WallPressures;
+
+// The number of collisions of each kind since the world was created: with walls, between balls, and
+// with obstacles
+pub struct CollisionCounts;
+
+
+// The number of collisions over a sliding window of simulated time
+pub struct CollisionCounter {
+    window: f64,
+    // The time and the numbers of collisions between balls and of all collisions of each ball,
+    // after each recent update
+    samples: VecDeque<(f64, u64, u64)>,
+}
+
+
+impl Readout {
+    pub fn new(observable: Box<dyn Observable>, scale: f64, unit: &str) -> Readout {
+        Readout{observable, scale, unit: String::from(unit)}
+    }
+
+
+    // In simulation units
+    pub fn unscaled(observable: Box<dyn Observable>) -> Readout {
+        Readout::new(observable, 1.0, "")
+    }
+
+
+    // The statistics shown by default
+    pub fn default_panel() -> Vec<Readout> {
+        vec![Readout::unscaled(Box::new(BallCount)),
+             Readout::unscaled(Box::new(Temperature)),
+             Readout::unscaled(Box::new(TotalEnergy)),
+             Readout::unscaled(Box::new(Equipartition)),
+             Readout::new(Box::new(CollisionRate::new(1.0)), 1.0, "/s"),
+             Readout::new(Box::new(MeanFreeTime::new(1.0)), 1000.0, "ms"),
+             Readout::unscaled(Box::new(MeanFreePath::new(1.0)))]
+    }
+
+
+    // Sets the scale and unit of the readout with the given name in panel
+    pub fn set_unit(panel: &mut [Readout], name: &str, scale: f64, unit: &str) -> Result<(), String> {
+        let readout = panel.iter_mut()
+            .find(|r| r.observable.name() == name)
+            .ok_or(format!("no readout called {}", name))?;
+        readout.scale = scale;
+        readout.unit = String::from(unit);
+        Ok(())
+    }
+
+
+    // For example "temperature: 1234.5 K", or "name: a, b" for several values
+    pub fn text(&self, world: &World) -> String {
+        let values: Vec<String> = self.observable.values(world).iter()
+            .map(|v| format_value(v * self.scale))
+            .collect();
+        let unit = if self.unit.is_empty() { String::new() } else { format!(" {}", self.unit) };
+        format!("{}: {}{}", self.observable.name(), values.join(", "), unit)
+    }
+}
+
+
+// The observable with the given name, for choosing observables by name on the command line
+pub fn named(name: &str) -> Option<Box<dyn Observable>> {
+    let observable: Box<dyn Observable> = match name {
+        "balls" => Box::new(BallCount),
+        "temperature" => Box::new(Temperature),
+        "energy" => Box::new(TotalEnergy),
+        "energy per degree of freedom" => Box::new(Equipartition),
+        "collision rate" => Box::new(CollisionRate::new(1.0)),
+        "mean free time" => Box::new(MeanFreeTime::new(1.0)),
+        "mean free path" => Box::new(MeanFreePath::new(1.0)),
+        "momentum" => Box::new(Momentum),
+        "wall pressure" => Box::new(WallPressures),
+        "collisions" => Box::new(CollisionCounts),
+        _ => return None,
+    };
+    Some(observable)
+}
+
+
+fn format_value(value: f64) -> String {
+    if value.fract() == 0.0 && value.abs() < 1e6 {
+        format!("{:.0}", value)
+    } else if value.abs() >= 1e6 || value.abs() < 1e-2 {
+        format!("{:.3e}", value)
+    } else {
+        format!("{:.2}", value)
+    }
+}
+
+
+impl Observable for BallCount {
+    fn name(&self) -> String {
+        String::from("balls")
+    }
+
+
+    fn values(&self, world: &World) -> Vec<f64> {
+        vec![world.get_balls().len() as f64]
+    }
+}
+
+
+impl Observable for Temperature {
+    fn name(&self) -> String {
+        String::from("temperature")
+    }
+
+
+    fn values(&self, world: &World) -> Vec<f64> {
+        vec![statistics::temperature(world.get_balls())]
+    }
+}
+
+
+impl Observable for TotalEnergy {
+    fn name(&self) -> String {
+        String::from("energy")
+    }
+
+
+    fn values(&self, world: &World) -> Vec<f64> {
+        vec![world.total_energy()]
+    }
+}
+
+
+impl Observable for Equipartition {
+    fn name(&self) -> String {
+        String::from("energy per degree of freedom")
+    }
+
+
+    fn labels(&self, _world: &World) -> Vec<String> {
+        vec![String::from("translational energy per degree of freedom"), String::from("rotational energy per degree of freedom")]
+    }
+
+
+    fn values(&self, world: &World) -> Vec<f64> {
+        let balls = world.get_balls();
+        if balls.is_empty() {
+            return vec![0.0, 0.0];
+        }
+        let n = balls.len() as f64;
+        let rotational: f64 = balls.iter().map(|b| b.rotational_energy() as f64).sum();
+        // Two translational degrees of freedom per ball, and one rotational
+        vec![statistics::temperature(balls) / 2.0, rotational / n]
+    }
+}
+
+
+impl CollisionCounter {
+    pub fn new(window: f64) -> CollisionCounter {
+        CollisionCounter{window, samples: VecDeque::new()}
+    }
+
+
+    pub fn observe(&mut self, world: &World) {
+        let between = world.collision_total(CollisionKind::Ball);
+        let all = 2 * between + world.collision_total(CollisionKind::Wall) + world.collision_total(CollisionKind::Obstacle);
+        let time = world.get_time();
+        self.samples.push_back((time, between, all));
+        while self.samples.len() > 2 && (time - self.samples[1].0).abs() >= self.window {
+            self.samples.pop_front();
+        }
+    }
+
+
+    // Collisions between balls, and collisions of any one ball, per unit time over the window
+    pub fn rates(&self) -> Option<(f64, f64)> {
+        let (first, last) = (self.samples.front()?, self.samples.back()?);
+        let duration = (last.0 - first.0).abs();
+        if duration <= 0.0 {
+            return None;
+        }
+        Some(((last.1 - first.1) as f64 / duration, (last.2 - first.2) as f64 / duration))
+    }
+}
+
+
+impl CollisionRate {
+    pub fn new(window: f64) -> CollisionRate {
+        CollisionRate{counter: CollisionCounter::new(window)}
+    }
+}
+
+
+impl Observable for CollisionRate {
+    fn name(&self) -> String {
+        String::from("collision rate")
+    }
+
+
+    fn observe(&mut self, world: &World) {
+        self.counter.observe(world);
+    }
+
+
+    fn values(&self, _world: &World) -> Vec<f64> {
+        vec![self.counter.rates().map_or(0.0, |r| r.0)]
+    }
+}
+
+
+impl MeanFreeTime {
+    pub fn new(window: f64) -> MeanFreeTime {
+        MeanFreeTime{counter: CollisionCounter::new(window)}
+    }
+}
+
+
+// The mean time between collisions of a ball, infinite if there were none in the window
+fn mean_free_time(counter: &CollisionCounter, world: &World) -> f64 {
+    match counter.rates() {
+        Some((_, all)) if all > 0.0 => world.get_balls().len() as f64 / all,
+        _ => f64::INFINITY,
+    }
+}
+
+
+impl Observable for MeanFreeTime {
+    fn name(&self) -> String {
+        String::from("mean free time")
+    }
+
+
+    fn observe(&mut self, world: &World) {
+        self.counter.observe(world);
+    }
+
+
+    fn values(&self, world: &World) -> Vec<f64> {
+        vec![mean_free_time(&self.counter, world)]
+    }
+}
+
+
+impl MeanFreePath {
+    pub fn new(window: f64) -> MeanFreePath {
+        MeanFreePath{counter: CollisionCounter::new(window)}
+    }
+}
+
+
+impl Observable for MeanFreePath {
+    fn name(&self) -> String {
+        String::from("mean free path")
+    }
+
+
+    fn observe(&mut self, world: &World) {
+        self.counter.observe(world);
+    }
+
+
+    // The mean speed times the mean free time
+    fn values(&self, world: &World) -> Vec<f64> {
+        let balls = world.get_balls();
+        if balls.is_empty() {
+            return vec![0.0];
+        }
+        let mean_speed = balls.iter()
+            .map(|b| vector_math::length_squared(&b.velocity).sqrt() as f64)
+            .sum::<f64>() / balls.len() as f64;
+        vec![mean_speed * mean_free_time(&self.counter, world)]
+    }
+}
+
+
+impl Observable for Momentum {
+    fn name(&self) -> String {
+        String::from("momentum")
+    }
+
+
+    fn labels(&self, _world: &World) -> Vec<String> {
+        vec![String::from("momentum x"), String::from("momentum y")]
+    }
+
+
+    fn values(&self, world: &World) -> Vec<f64> {
+        let momentum = world.momentum();
+        vec![momentum.x, momentum.y]
+    }
+}
+
+
+impl Observable for WallPressures {
+    fn name(&self) -> String {
+        String::from("wall pressure")
+    }
+
+
+    fn labels(&self, world: &World) -> Vec<String> {
+        (0..world.get_walls().len()).map(|i| format!("pressure on wall {}", i)).collect()
+    }
+
+
+    fn values(&self, world: &World) -> Vec<f64> {
+        world.wall_pressures().unwrap_or_else(|| vec![f64::NAN; world.get_walls().len()])
+    }
+}
+
+
+impl Observable for CollisionCounts {
+    fn name(&self) -> String {
+        String::from("collisions")
+    }
+
+
+    fn labels(&self, _world: &World) -> Vec<String> {
+        vec![String::from("wall collisions"), String::from("ball collisions"), String::from("obstacle collisions")]
+    }
+
+
+    fn values(&self, world: &World) -> Vec<f64> {
+        [CollisionKind::Wall, CollisionKind::Ball, CollisionKind::Obstacle].iter()
+            .map(|&kind| world.collision_total(kind) as f64)
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ball::Ball;
+    use super::super::geometry::Rect;
+    use super::super::vector_math::Vector2f;
+
+    #[test]
+    fn collision_rates_over_the_window() {
+        // One ball crossing a box, hitting a wall every second from 0.375 on
+        let mut world = World::with_box(&Rect{left: 0.0, top: 0.0, width: 100.0, height: 100.0});
+        world.add_ball(Ball::new(Vector2f{x: 50.0, y: 50.0}, Vector2f{x: 80.0, y: 0.0}, 10.0, 1.0));
+        let mut counter = CollisionCounter::new(4.0);
+        counter.observe(&world);
+        assert_eq!(counter.rates(), None);
+
+        for _ in 0..600 {
+            world.update(1.0 / 60.0);
+            counter.observe(&world);
+        }
+        // The window runs from 6 to 10, over the hits at 6.375, 7.375, 8.375 and 9.375
+        let (between, all) = counter.rates().unwrap();
+        assert_eq!(between, 0.0);
+        assert!((all - 1.0).abs() < 1e-3, "{} collisions per unit time", all);
+        assert_eq!(world.collision_total(CollisionKind::Wall), 10);
+    }
+}
@@ -0,0 +1,69 @@
+// Fixed circular scatterers, as in the Sinai billiard and the Lorentz gas. Balls bounce off them as
+// they would off a ball of infinite mass that never moves.
+use serde::{Serialize, Deserialize};
+use super::ball::Ball;
+use super::plane::Plane;
+use super::vector_math::{self, Vector2f};
+use super::restitution::default_restitution;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Obstacle {
+    pub position: Vector2f,
+    pub radius: f32,
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    #[serde(default)]
+    pub friction: f32,
+}
+
+
+impl Obstacle {
+    pub fn new(position: Vector2f, radius: f32) -> Obstacle {
+        Obstacle{position, radius, restitution: 1.0, friction: 0.0}
+    }
+
+
+    // A stationary ball in the obstacle's place, for collision prediction
+    pub fn as_ball(&self) -> Ball {
+        Ball::new(self.position, Vector2f::default(), self.radius, 1.0)
+    }
+
+
+    // The plane touching the obstacle where it is nearest to point, facing it. From the centre, where
+    // every point on the surface is nearest, the one in the x direction is taken.
+    pub fn contact_plane(&self, point: Vector2f) -> Plane {
+        let mut normal = point - self.position;
+        if normal == Vector2f::default() {
+            normal = Vector2f{x: 1.0, y: 0.0};
+        }
+        let length = vector_math::length_squared(&normal).sqrt();
+        let contact = self.position + normal * (self.radius / length);
+        Plane{restitution: self.restitution, friction: self.friction, ..Plane::new(contact, normal)}
+    }
+
+
+    // From point to the surface, negative inside
+    pub fn distance(&self, point: Vector2f) -> f32 {
+        vector_math::length_squared(&(point - self.position)).sqrt() - self.radius
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contact_plane_faces_the_point() {
+        let obstacle = Obstacle::new(Vector2f{x: 10.0, y: 10.0}, 5.0);
+        let plane = obstacle.contact_plane(Vector2f{x: 13.0, y: 14.0});
+        assert_eq!(plane.normal, Vector2f{x: 0.6, y: 0.8});
+        assert_eq!(plane.position, Vector2f{x: 13.0, y: 14.0});
+        assert_eq!(obstacle.distance(Vector2f{x: 13.0, y: 14.0}), 0.0);
+
+        let centre = obstacle.contact_plane(obstacle.position);
+        assert_eq!(centre.normal, Vector2f{x: 1.0, y: 0.0});
+        assert_eq!(centre.position, Vector2f{x: 15.0, y: 10.0});
+    }
+}
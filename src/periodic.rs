@@ -0,0 +1,71 @@
+// Periodic boundaries: on a periodic axis, a ball leaving one side of the cell comes back in on the
+// other, and balls near opposite sides interact across the boundary as if the cell were repeated
+// forever. Distances on those axes are measured to the nearest copy (the minimum image), which is
+// only right while balls move less than half the cell between collisions.
+use serde::{Serialize, Deserialize};
+use super::geometry::Rect;
+use super::vector_math::Vector2f;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Periodic {
+    pub cell: Rect,
+    pub x: bool,
+    pub y: bool,
+}
+
+
+impl Periodic {
+    pub fn new(cell: Rect, x: bool, y: bool) -> Periodic {
+        Periodic{cell, x, y}
+    }
+
+
+    pub fn is_periodic(&self) -> bool {
+        self.x || self.y
+    }
+
+
+    // The copy of point inside the cell
+    pub fn wrap(&self, point: Vector2f) -> Vector2f {
+        let wrap = |p: f32, start: f32, length: f32| start + (p - start).rem_euclid(length);
+        Vector2f{x: if self.x {wrap(point.x, self.cell.left, self.cell.width)} else {point.x},
+                 y: if self.y {wrap(point.y, self.cell.top, self.cell.height)} else {point.y}}
+    }
+
+
+    // The shortest displacement between copies of two points that are displacement apart
+    pub fn minimum_image(&self, displacement: Vector2f) -> Vector2f {
+        let nearest = |d: f32, length: f32| d - length * (d / length).round();
+        Vector2f{x: if self.x {nearest(displacement.x, self.cell.width)} else {displacement.x},
+                 y: if self.y {nearest(displacement.y, self.cell.height)} else {displacement.y}}
+    }
+
+
+    // The copy of point nearest to target
+    pub fn nearest_image(&self, point: Vector2f, target: Vector2f) -> Vector2f {
+        target + self.minimum_image(point - target)
+    }
+
+
+    // The offsets of the copies of a box that overlap the cell, besides the box itself. Only boxes
+    // that cross the edges of the cell have any.
+    pub fn image_offsets(&self, min: Vector2f, max: Vector2f) -> Vec<Vector2f> {
+        let shifts = |periodic: bool, min: f32, max: f32, start: f32, length: f32| {
+            let mut shifts = vec![0.0];
+            if periodic && min < start {
+                shifts.push(length);
+            }
+            if periodic && max > start + length {
+                shifts.push(-length);
+            }
+            shifts
+        };
+        let xs = shifts(self.x, min.x, max.x, self.cell.left, self.cell.width);
+        let ys = shifts(self.y, min.y, max.y, self.cell.top, self.cell.height);
+        xs.iter()
+            .flat_map(|&x| ys.iter().map(move |&y| Vector2f{x, y}))
+            .filter(|offset| *offset != Vector2f::default())
+            .collect()
+    }
+}
@@ -1,7 +1,65 @@
-use sfml::system::Vector2f;
+use super::vector_math;
+use super::vector_math::Vector2f;
+use serde::{Serialize, Deserialize};
+use super::restitution::default_restitution;
 
 
+// An infinite wall through position. Balls are kept on the side the normal points towards.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Plane {
     pub position: Vector2f,
     pub normal: Vector2f,
+    // Combined with the ball's coefficient by the world's restitution rule
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    // The coefficient of friction, combined with the ball's by the world's friction rule
+    #[serde(default)]
+    pub friction: f32,
+    #[serde(default)]
+    pub motion: Motion,
+    // Makes the wall a heat bath at this temperature, see Ball::resolve_thermal_collision
+    #[serde(default)]
+    pub temperature: Option<f32>,
+}
+
+
+// How a plane moves: at a constant velocity, plus back and forth by amplitude * sin(2 pi t / period).
+// Through each call to World::update it moves at the constant velocity that takes it to where it
+// should be at the end, so that collisions with it can still be predicted exactly.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Motion {
+    #[serde(default)]
+    pub velocity: Vector2f,
+    #[serde(default)]
+    pub amplitude: Vector2f,
+    // Only used with an amplitude
+    #[serde(default)]
+    pub period: f32,
+}
+
+
+impl Plane {
+    // The normal need not be of unit length
+    pub fn new(position: Vector2f, normal: Vector2f) -> Plane {
+        let length = vector_math::length_squared(&normal).sqrt();
+        Plane{position, normal: normal / length, restitution: 1.0, friction: 0.0, motion: Motion::default(),
+              temperature: None}
+    }
+}
+
+
+impl Motion {
+    pub fn is_fixed(&self) -> bool {
+        self.velocity == Vector2f::default() && self.amplitude == Vector2f::default()
+    }
+
+
+    // The constant velocity that moves the plane from where it is at time to where it is dt later
+    pub fn velocity(&self, time: f64, dt: f32) -> Vector2f {
+        if self.amplitude == Vector2f::default() {
+            return self.velocity;
+        }
+        let phase = |t: f64| (2.0 * std::f64::consts::PI * t / self.period as f64).sin();
+        self.velocity + self.amplitude * ((phase(time + dt as f64) - phase(time)) / dt as f64) as f32
+    }
 }
@@ -0,0 +1,214 @@
+// Soft interactions, as an alternative to hard disks. Balls interact through a pair potential and are
+// moved in small fixed time steps by velocity Verlet, which like the event-driven dynamics can be
+// run backwards. Distances are measured in units of the contact distance sigma: the sum of the radii
+// of two balls, or the radius of a ball meeting a wall.
+use serde::{Serialize, Deserialize};
+use super::ball::Ball;
+use super::wall::Wall;
+use super::obstacle::Obstacle;
+use super::field::Field;
+use super::periodic::Periodic;
+use super::vector_math::{self, Vector2f};
+
+
+// How World::update moves the balls
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum Integrator {
+    // Exact hard disk dynamics, from one collision to the next
+    #[default]
+    EventDriven,
+    // Velocity Verlet with the given potential, in steps of at most max_step
+    Verlet { potential: Potential, max_step: f32 },
+    // Verlet on an integer lattice, after Levesque and Verlet: positions are multiples of resolution,
+    // and each step the force is rounded to the lattice and added in integer arithmetic, so that
+    // running backwards retraces every step exactly. Steps are always of length step, taken when the
+    // simulated time passes a multiple of it. Walls do not move, and spin is left alone.
+    Exact { potential: Potential, step: f32, resolution: f32 },
+}
+
+
+impl Integrator {
+    // The potential between soft balls, or None for hard ones
+    pub fn potential(&self) -> Option<Potential> {
+        match *self {
+            Integrator::EventDriven => None,
+            Integrator::Verlet{potential, ..} | Integrator::Exact{potential, ..} => Some(potential),
+        }
+    }
+}
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Potential {
+    // 4 epsilon ((sigma/r)^12 - (sigma/r)^6), cut off and shifted to zero at cutoff * sigma
+    LennardJones { epsilon: f32, cutoff: f32 },
+    // Weeks-Chandler-Andersen: the repulsive part of Lennard-Jones, cut off at its minimum
+    Wca { epsilon: f32 },
+    // stiffness * (sigma - r)^2 / 2 while the balls overlap
+    Harmonic { stiffness: f32 },
+}
+
+
+impl Potential {
+    // The distance beyond which there is no interaction
+    pub fn range(&self, sigma: f32) -> f32 {
+        match *self {
+            Potential::LennardJones{cutoff, ..} => cutoff * sigma,
+            Potential::Wca{..} => 2f32.powf(1.0 / 6.0) * sigma,
+            Potential::Harmonic{..} => sigma,
+        }
+    }
+
+
+    pub fn energy(&self, r: f32, sigma: f32) -> f32 {
+        if r >= self.range(sigma) {
+            return 0.0;
+        }
+        match *self {
+            Potential::LennardJones{epsilon, cutoff} => lennard_jones(epsilon, r / sigma) - lennard_jones(epsilon, cutoff),
+            Potential::Wca{epsilon} => lennard_jones(epsilon, r / sigma) + epsilon,
+            Potential::Harmonic{stiffness} => 0.5 * stiffness * (sigma - r).powi(2),
+        }
+    }
+
+
+    // The magnitude of the force at distance r, positive when repulsive
+    pub fn force(&self, r: f32, sigma: f32) -> f32 {
+        if r >= self.range(sigma) {
+            return 0.0;
+        }
+        match *self {
+            Potential::LennardJones{epsilon, ..} | Potential::Wca{epsilon} => {
+                let s6 = (sigma / r).powi(6);
+                24.0 * epsilon * (2.0 * s6 * s6 - s6) / r
+            },
+            Potential::Harmonic{stiffness} => stiffness * (sigma - r),
+        }
+    }
+
+
+    // Walls only repel, so Lennard-Jones becomes WCA against them
+    pub fn for_walls(&self) -> Potential {
+        match *self {
+            Potential::LennardJones{epsilon, ..} => Potential::Wca{epsilon},
+            other => other,
+        }
+    }
+
+
+    // As force and energy, for a ball whose centre is at distance from a wall. A ball pushed past a
+    // plane or into an obstacle has a negative distance, at which the Lennard-Jones terms would pull it
+    // further in, so they take the distance as at least MINIMUM_WALL_DISTANCE * sigma instead: the push
+    // back along the normal stays large and finite. The harmonic force just keeps growing.
+    pub fn wall_force(&self, distance: f32, sigma: f32) -> f32 {
+        self.force(self.wall_distance(distance, sigma), sigma)
+    }
+
+
+    pub fn wall_energy(&self, distance: f32, sigma: f32) -> f32 {
+        self.energy(self.wall_distance(distance, sigma), sigma)
+    }
+
+
+    fn wall_distance(&self, distance: f32, sigma: f32) -> f32 {
+        match *self {
+            Potential::Harmonic{..} => distance,
+            _ => distance.max(MINIMUM_WALL_DISTANCE * sigma),
+        }
+    }
+}
+
+
+// Closer than this, as a fraction of sigma, Lennard-Jones forces against walls no longer grow
+const MINIMUM_WALL_DISTANCE: f32 = 0.5;
+
+
+fn lennard_jones(epsilon: f32, r: f32) -> f32 {
+    let s6 = r.powi(-6);
+    4.0 * epsilon * (s6 * s6 - s6)
+}
+
+
+// The acceleration of each ball due to the other balls, the walls, the obstacles and the field. pairs holds every
+// pair of balls close enough to interact, and may hold others. Balls and obstacles interact with the nearest
+// periodic copies of each other.
+pub fn accelerations(balls: &[Ball], walls: &[Wall], obstacles: &[Obstacle], potential: &Potential, field: &Field,
+                     periodic: &Periodic, pairs: &[(usize, usize)]) -> Vec<Vector2f> {
+    let mut forces = vec![Vector2f::default(); balls.len()];
+    for &(i, j) in pairs {
+        let axis = periodic.minimum_image(balls[i].get_position() - balls[j].get_position());
+        let r = vector_math::length_squared(&axis).sqrt();
+        let f = potential.force(r, balls[i].circle.radius + balls[j].circle.radius);
+        if f != 0.0 {
+            let force = axis * (f / r);
+            forces[i] += force;
+            forces[j] -= force;
+        }
+    }
+
+    let wall_potential = potential.for_walls();
+    for (ball, force) in balls.iter().zip(forces.iter_mut()) {
+        for wall in walls {
+            let p = ball.get_position();
+            let f = wall_potential.wall_force(wall.distance(p), ball.circle.radius);
+            if f != 0.0 {
+                *force += wall.contact_plane(p).normal * f;
+            }
+        }
+        for obstacle in obstacles {
+            let p = periodic.nearest_image(ball.get_position(), obstacle.position);
+            let f = wall_potential.wall_force(obstacle.distance(p), ball.circle.radius);
+            if f != 0.0 {
+                *force += obstacle.contact_plane(p).normal * f;
+            }
+        }
+    }
+
+    balls.iter().zip(forces)
+        .map(|(ball, force)| force / ball.get_mass() + field.acceleration(ball))
+        .collect()
+}
+
+
+// The potential energy of the interactions between balls and with the walls and obstacles
+pub fn interaction_energy(balls: &[Ball], walls: &[Wall], obstacles: &[Obstacle], potential: &Potential, periodic: &Periodic,
+                          pairs: &[(usize, usize)]) -> f64 {
+    let between: f64 = pairs.iter()
+        .map(|&(i, j)| {
+            let r = vector_math::length_squared(&periodic.minimum_image(balls[i].get_position() - balls[j].get_position())).sqrt();
+            potential.energy(r, balls[i].circle.radius + balls[j].circle.radius) as f64
+        })
+        .sum();
+
+    let wall_potential = potential.for_walls();
+    let with_walls: f64 = walls.iter().map(|wall| wall_energy(balls, wall, potential)).sum();
+    let with_obstacles: f64 = balls.iter()
+        .flat_map(|ball| obstacles.iter().map(move |obstacle| (ball, obstacle)))
+        .map(|(ball, obstacle)| {
+            let p = periodic.nearest_image(ball.get_position(), obstacle.position);
+            wall_potential.wall_energy(obstacle.distance(p), ball.circle.radius) as f64
+        })
+        .sum();
+
+    between + with_walls + with_obstacles
+}
+
+
+// The total force the balls push each wall with
+pub fn wall_forces(balls: &[Ball], walls: &[Wall], potential: &Potential) -> Vec<f64> {
+    let wall_potential = potential.for_walls();
+    walls.iter()
+        .map(|wall| balls.iter()
+            .map(|ball| wall_potential.wall_force(wall.distance(ball.get_position()), ball.circle.radius) as f64)
+            .sum())
+        .collect()
+}
+
+
+// The potential energy of the interactions between the balls and a single wall
+pub fn wall_energy(balls: &[Ball], wall: &Wall, potential: &Potential) -> f64 {
+    let wall_potential = potential.for_walls();
+    balls.iter()
+        .map(|ball| wall_potential.wall_energy(wall.distance(ball.get_position()), ball.circle.radius) as f64)
+        .sum()
+}
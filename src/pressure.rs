@@ -0,0 +1,71 @@
+// Pressure measured from the momentum the balls give the walls. The normal impulse on each wall is
+// summed over each update, and the force on it is the total over the recent updates in a sliding
+// window divided by the time they cover.
+use std::collections::VecDeque;
+
+
+pub struct PressureGauge {
+    // In simulated time
+    window: f64,
+    // The normal impulse on each wall so far in the current update
+    current: Vec<f64>,
+    // For each update in the window: when it ended, how long it lasted, and the impulse on each wall
+    frames: VecDeque<(f64, f64, Vec<f64>)>,
+}
+
+
+impl PressureGauge {
+    pub fn new(window: f64) -> PressureGauge {
+        PressureGauge{window, current: Vec::new(), frames: VecDeque::new()}
+    }
+
+
+    // Forgets everything measured, for when the walls change
+    pub fn reset(&mut self, walls: usize) {
+        self.current = vec![0.0; walls];
+        self.frames.clear();
+    }
+
+
+    pub fn add(&mut self, wall: usize, impulse: f64) {
+        self.current[wall] += impulse;
+    }
+
+
+    // Closes the current update, which ended at time and lasted duration
+    pub fn end_update(&mut self, time: f64, duration: f64) {
+        let walls = self.current.len();
+        let impulses = std::mem::replace(&mut self.current, vec![0.0; walls]);
+        self.frames.push_back((time, duration, impulses));
+        // Time can run either way, so the window reaches back from the latest update in either direction
+        while let Some(&(end, duration, _)) = self.frames.front() {
+            if (time - end).abs() + duration <= self.window || self.frames.len() == 1 {
+                break;
+            }
+            self.frames.pop_front();
+        }
+    }
+
+
+    // The mean force on each wall over the window, or None before any update
+    pub fn forces(&self) -> Option<Vec<f64>> {
+        let duration: f64 = self.frames.iter().map(|f| f.1).sum();
+        if duration <= 0.0 {
+            return None;
+        }
+        let forces = (0..self.current.len())
+            .map(|j| self.frames.iter().map(|f| f.2[j]).sum::<f64>() / duration)
+            .collect();
+        Some(forces)
+    }
+
+
+    pub fn get_window(&self) -> f64 {
+        self.window
+    }
+
+
+    pub fn set_window(&mut self, window: f64) {
+        self.window = window;
+    }
+}
@@ -0,0 +1,118 @@
+// Drawing of the physics types with SFML. Only built with the gui feature, so that the
+// physics itself has no dependency on SFML.
+use sfml::system::Vector2f;
+use sfml::graphics::{RenderWindow, RenderTarget, CircleShape, RectangleShape, Color, Transformable, Shape};
+use super::ball::Ball;
+use super::world::World;
+use super::wall::Wall;
+use super::obstacle::Obstacle;
+use super::vector_math;
+
+
+impl Ball {
+    pub fn draw(&self, window: &mut RenderWindow) {
+        let mut temp_circle = CircleShape::new(self.circle.radius, 32);
+
+        temp_circle.set_fill_color(&Color::RED);
+        temp_circle.set_position(Vector2f::from(self.circle.position));
+        temp_circle.set_origin(Vector2f{x: temp_circle.radius(), y: temp_circle.radius()});
+        window.draw(&temp_circle);
+
+        // A radius marking the orientation, so that spin is visible
+        let mut marker = RectangleShape::with_size(Vector2f{x: self.circle.radius, y: 2.0});
+        marker.set_fill_color(&Color::WHITE);
+        marker.set_origin(Vector2f{x: 0.0, y: 1.0});
+        marker.set_position(Vector2f::from(self.circle.position));
+        marker.set_rotation(self.orientation.to_degrees());
+        window.draw(&marker);
+    }
+}
+
+
+impl Obstacle {
+    // Blue and outlined, to tell them apart from the balls
+    pub fn draw(&self, window: &mut RenderWindow) {
+        let mut circle = CircleShape::new(self.radius, 48);
+        circle.set_fill_color(&Color::rgb(70, 110, 180));
+        circle.set_outline_color(&Color::rgb(125, 125, 125));
+        circle.set_outline_thickness(2.0);
+        circle.set_position(Vector2f::from(self.position));
+        circle.set_origin(Vector2f{x: self.radius, y: self.radius});
+        window.draw(&circle);
+    }
+}
+
+
+impl World {
+    pub fn draw(&self, window: &mut RenderWindow) {
+        for obstacle in self.get_obstacles() {
+            obstacle.draw(window);
+        }
+        let periodic = self.get_periodic();
+        for ball in self.get_balls() {
+            ball.draw(window);
+            // Balls straddling a periodic edge show on both sides of it
+            let r = vector_math::Vector2f{x: ball.circle.radius, y: ball.circle.radius};
+            for offset in periodic.image_offsets(ball.get_position() - r, ball.get_position() + r) {
+                let mut ghost = *ball;
+                ghost.circle.position += offset;
+                ghost.draw(window);
+            }
+        }
+        self.draw_walls(window);
+    }
+
+
+
+    fn draw_walls(&self, window: &mut RenderWindow) {
+        let thickness = 10.0;
+        let rect = self.bounding_rect();
+        for wall in self.get_walls() {
+            // Thermal walls glow
+            let color = if wall.temperature().is_some() { Color::rgb(230, 120, 40) } else { Color::rgb(125, 125, 125) };
+            match wall {
+                Wall::Plane(plane) => {
+                    // Planes are infinite, so draw as much as covers the bounding rectangle
+                    let along = vector_math::Vector2f{x: -plane.normal.y, y: plane.normal.x};
+                    let (from, to) = match rect {
+                        Some(rect) => {
+                            let corners = [(rect.left, rect.top), (rect.left + rect.width, rect.top),
+                                           (rect.left, rect.top + rect.height), (rect.left + rect.width, rect.top + rect.height)];
+                            let along_corners = corners.iter()
+                                .map(|&(x, y)| vector_math::dot_product(&along, &(vector_math::Vector2f{x, y} - plane.position)));
+                            let from = along_corners.clone().fold(f32::INFINITY, f32::min) - thickness;
+                            let to = along_corners.fold(f32::NEG_INFINITY, f32::max) + thickness;
+                            (from, to)
+                        },
+                        None => (-10000.0, 10000.0),
+                    };
+                    // Behind the plane, so that the balls' side of the strip is where they bounce
+                    let behind = plane.position - plane.normal * (0.5 * thickness);
+                    draw_line(window, behind + along * from, behind + along * to, thickness, color);
+                },
+                Wall::Segment(segment) => draw_line(window, segment.start, segment.end, 0.4 * thickness, color),
+                Wall::Arc(arc) => {
+                    // As a chain of short lines, enough for a whole circle to look round
+                    let pieces = (64.0 * arc.sweep.min(2.0 * std::f32::consts::PI) / (2.0 * std::f32::consts::PI)).ceil().max(1.0);
+                    let point = |i: f32| arc.centre + vector_math::rotate(&vector_math::Vector2f{x: arc.radius, y: 0.0},
+                                                                         arc.start_angle + arc.sweep * i / pieces);
+                    for i in 0..pieces as usize {
+                        draw_line(window, point(i as f32), point(i as f32 + 1.0), 0.4 * thickness, color);
+                    }
+                },
+            }
+        }
+    }
+}
+
+
+fn draw_line(window: &mut RenderWindow, from: vector_math::Vector2f, to: vector_math::Vector2f, thickness: f32, color: Color) {
+    let direction = to - from;
+    let length = vector_math::length_squared(&direction).sqrt();
+    let mut shape = RectangleShape::with_size(Vector2f{x: length, y: thickness});
+    shape.set_fill_color(&color);
+    shape.set_origin(Vector2f{x: 0.0, y: 0.5 * thickness});
+    shape.set_position(Vector2f::from(from));
+    shape.set_rotation(vector_math::angle_rad(&direction).to_degrees());
+    window.draw(&shape);
+}
@@ -0,0 +1,73 @@
+// Coefficients of restitution: the fraction of the normal relative speed that survives a collision.
+// 1 is perfectly elastic, 0 perfectly inelastic. Also the coefficients of friction that limit the
+// tangential impulse, which are combined in the same way.
+use serde::{Serialize, Deserialize};
+
+
+// How the coefficients of the two bodies in a collision are combined
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum CombineRule {
+    #[default]
+    Min,
+    Product,
+    Average,
+}
+
+
+// Inelastic collapse: with restitution below 1, a group of balls can collide infinitely often in a
+// finite time, which would stall World::update. These make some collisions elastic to prevent it.
+// The default is a contact duration of 10 ms, which also keeps a ball coming to rest on the floor
+// under gravity bouncing often enough for update to finish, for gravity from a few units per second
+// squared up to thousands. Much shorter durations let the bounces shrink to nothing through rounding.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CollapseGuard {
+    // No guard, for worlds where collapse can't happen. An inelastic one can then stall update.
+    None,
+    // Collisions with a normal relative speed below this are elastic
+    MinimumSpeed(f32),
+    // The TC model: a collision is elastic if either body collided less than this long before
+    ContactDuration(f64),
+}
+
+
+impl CombineRule {
+    pub fn combine(&self, a: f32, b: f32) -> f32 {
+        match self {
+            CombineRule::Min     => a.min(b),
+            CombineRule::Product => a * b,
+            CombineRule::Average => 0.5 * (a + b),
+        }
+    }
+}
+
+
+impl Default for CollapseGuard {
+    fn default() -> CollapseGuard {
+        CollapseGuard::ContactDuration(0.01)
+    }
+}
+
+
+impl CollapseGuard {
+    // The coefficient to use, given the combined one, the normal speed at which the bodies meet and the
+    // time since whichever of them collided most recently last did so
+    pub fn apply(&self, restitution: f32, normal_speed: f32, since_last: f64) -> f32 {
+        let elastic = match *self {
+            CollapseGuard::None => false,
+            CollapseGuard::MinimumSpeed(speed) => normal_speed.abs() < speed,
+            CollapseGuard::ContactDuration(tc) => since_last < tc,
+        };
+        if elastic { 1.0 } else { restitution }
+    }
+}
+
+
+pub fn default_restitution() -> f32 {
+    1.0
+}
+
+
+// Solid disks
+pub fn default_inertia_factor() -> f32 {
+    0.5
+}
@@ -0,0 +1,71 @@
+// The reverse-and-return experiment: run a world forwards through a sequence of updates, then backwards
+// through the same updates in reverse order, and measure how far each ball ends up from where it
+// started. Collisions between hard disks magnify rounding error exponentially, so beyond a few
+// collisions per ball they no longer return; with Integrator::Exact they return exactly.
+use super::world::World;
+use super::vector_math::{self, Vector2f};
+
+
+// How far each ball is from its starting state after the return
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reversal {
+    // Minimum image distances on periodic axes
+    pub displacements: Vec<f32>,
+    // The size of the change in each ball's velocity
+    pub velocity_errors: Vec<f32>,
+}
+
+
+// Updates world by each of steps in turn, then by the negative of each in reverse order. With
+// Integrator::Exact, the balls' starting state is their state rounded to the lattice.
+pub fn reverse_and_return(world: &mut World, steps: &[f32]) -> Reversal {
+    world.quantize();
+    let start: Vec<(Vector2f, Vector2f)> = world.get_balls().iter().map(|b| (b.get_position(), b.velocity)).collect();
+    for &dt in steps {
+        world.update(dt);
+    }
+    for &dt in steps.iter().rev() {
+        world.update(-dt);
+    }
+
+    let periodic = world.get_periodic();
+    let size = |v: Vector2f| vector_math::length_squared(&v).sqrt();
+    let (displacements, velocity_errors) = world.get_balls().iter().zip(&start)
+        .map(|(ball, (position, velocity))| {
+            (size(periodic.minimum_image(ball.get_position() - *position)), size(ball.velocity - *velocity))
+        })
+        .unzip();
+    Reversal{displacements, velocity_errors}
+}
+
+
+impl Reversal {
+    // Whether every ball is back exactly where it started, at exactly its starting velocity
+    pub fn is_exact(&self) -> bool {
+        self.displacements.iter().chain(&self.velocity_errors).all(|&e| e == 0.0)
+    }
+
+
+    pub fn max_displacement(&self) -> f32 {
+        self.displacements.iter().copied().fold(0.0, f32::max)
+    }
+
+
+    pub fn rms_displacement(&self) -> f32 {
+        if self.displacements.is_empty() {
+            return 0.0;
+        }
+        (self.displacements.iter().map(|d| d * d).sum::<f32>() / self.displacements.len() as f32).sqrt()
+    }
+
+
+    pub fn max_velocity_error(&self) -> f32 {
+        self.velocity_errors.iter().copied().fold(0.0, f32::max)
+    }
+
+
+    // The number of balls that ended up more than tolerance from where they started
+    pub fn count_displaced(&self, tolerance: f32) -> usize {
+        self.displacements.iter().filter(|&&d| d > tolerance).count()
+    }
+}
@@ -0,0 +1,973 @@
+// Scene files: a declarative description of a World, stored as RON. For example
+//
+//   (
+//       seed: Some(42),
+//       container: Box((left: 0.0, top: 0.0, width: 800.0, height: 800.0)),
+//       balls: [
+//           (position: (x: 100.0, y: 100.0), velocity: (x: 50.0, y: 0.0), radius: 16.0, mass: 1.0),
+//       ],
+//       spawn: [
+//           (count: 50, region: (left: 0.0, top: 400.0, width: 800.0, height: 400.0),
+//            radius: 8.0, mass: 1.0, friction: 0.3, speed: MaxwellBoltzmann(temperature: 1000.0)),
+//       ],
+//       tangential_restitution: 0.0,
+//       field: Gravity((x: 0.0, y: 200.0)),
+//   )
+//
+// A box container can be made periodic on either axis or both with periodic: (true, false), which
+// replaces the box's walls on that axis with wrapping around to the other side, as for channel flow.
+// Baffles and obstacles are not repeated across periodic edges.
+use std::fmt;
+use std::fs;
+use serde::{Serialize, Deserialize};
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use super::ball::Ball;
+use super::wall::{Wall, Segment, CircularArc};
+use super::plane::Plane;
+use super::obstacle::Obstacle;
+use super::world::World;
+use super::geometry::Rect;
+use super::broad_phase::SpatialGrid;
+use super::vector_math::{self, Vector2f};
+use super::field::Field;
+use super::potential::{Integrator, Potential};
+use super::periodic::Periodic;
+use super::restitution::{CombineRule, CollapseGuard, default_restitution, default_inertia_factor};
+use super::math::standard_normal;
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scene {
+    // Seeds the random placement and velocities of spawned balls. If None, a seed is picked at random.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    pub container: Container,
+    // Walls inside the container, such as baffles
+    #[serde(default)]
+    pub baffles: Vec<Wall>,
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+    #[serde(default)]
+    pub lattices: Vec<Lattice>,
+    #[serde(default)]
+    pub balls: Vec<BallSpec>,
+    #[serde(default)]
+    pub spawn: Vec<SpawnRegion>,
+    #[serde(default)]
+    pub restitution_rule: CombineRule,
+    #[serde(default)]
+    pub collapse_guard: CollapseGuard,
+    #[serde(default)]
+    pub friction_rule: CombineRule,
+    #[serde(default)]
+    pub tangential_restitution: f32,
+    #[serde(default)]
+    pub field: Field,
+    #[serde(default)]
+    pub integrator: Integrator,
+    // Whether a Box container wraps around in x and in y
+    #[serde(default)]
+    pub periodic: (bool, bool),
+}
+
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Container {
+    // Four walls around a rectangle, with the given coefficient of restitution (1 if left out)
+    Box(Rect, #[serde(default = "default_restitution")] f32),
+    // A closed polygon of segment walls through the given vertices. Balls must be placed inside it.
+    Polygon(Vec<Vector2f>),
+    // A circular wall. Balls must be placed inside it.
+    Circle { centre: Vector2f, radius: f32 },
+    // Bunimovich's stadium: two semicircles joined by horizontal straight walls of the given length.
+    // Balls must be placed inside it.
+    Stadium { centre: Vector2f, length: f32, radius: f32 },
+    // Any set of walls
+    Walls(Vec<Wall>),
+}
+
+
+// A grid of identical obstacles, at origin + i * a + j * b for i below counts.0 and j below counts.1.
+// For example a = (x: d, y: 0), b = (x: 0, y: d) is a square lattice of spacing d, and
+// b = (x: d / 2, y: d * sqrt(3) / 2) a triangular one.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Lattice {
+    pub origin: Vector2f,
+    pub a: Vector2f,
+    pub b: Vector2f,
+    pub counts: (usize, usize),
+    pub radius: f32,
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    #[serde(default)]
+    pub friction: f32,
+}
+
+
+// A single ball, placed exactly
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct BallSpec {
+    pub position: Vector2f,
+    #[serde(default)]
+    pub velocity: Vector2f,
+    pub radius: f32,
+    pub mass: f32,
+    #[serde(default)]
+    pub angular_velocity: f32,
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    #[serde(default)]
+    pub friction: f32,
+    #[serde(default = "default_inertia_factor")]
+    pub inertia_factor: f32,
+}
+
+
+// A number of identical balls placed at random, without overlapping, inside a rectangle.
+// They move in random directions with speeds drawn from the given distribution.
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct SpawnRegion {
+    pub count: usize,
+    pub region: Rect,
+    pub radius: f32,
+    pub mass: f32,
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    #[serde(default)]
+    pub friction: f32,
+    #[serde(default = "default_inertia_factor")]
+    pub inertia_factor: f32,
+    pub speed: Speed,
+}
+
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum Speed {
+    Fixed(f32),
+    Uniform { min: f32, max: f32 },
+    // Each velocity component is normally distributed with variance temperature / mass,
+    // taking Boltzmann's constant as 1
+    MaxwellBoltzmann { temperature: f32 },
+}
+
+
+// The entry in a scene a validation error refers to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Entry {
+    Container,
+    // Index into Scene::balls
+    Ball(usize),
+    // Index into Scene::spawn
+    Spawn(usize),
+    // Index into Scene::obstacles
+    Obstacle(usize),
+    // Index into Scene::lattices
+    Lattice(usize),
+}
+
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(String),
+    NonPositiveMass(Entry),
+    NonPositiveRadius(Entry),
+    // Restitution outside [0, 1]
+    InvalidRestitution(Entry),
+    NegativeFriction(Entry),
+    NonPositiveInertia(Entry),
+    // A ball that is not entirely on the inner side of every wall
+    OutsideWalls(Entry),
+    // A ball overlapping an obstacle
+    OnObstacle(Entry),
+    InvalidObstacle(Entry, String),
+    Overlap(Entry, Entry),
+    // A spawn region with bad parameters, or too crowded to place all of its balls
+    InvalidSpawn(usize, String),
+    InvalidContainer(String),
+    InvalidTangentialRestitution,
+    InvalidIntegrator(String),
+}
+
+
+// The most attempts at finding a free spot for each spawned ball
+const MAX_SPAWN_ATTEMPTS: usize = 1000;
+
+// Relative to the radii involved, how far balls may overlap each other or the walls before a scene
+// is rejected. Balls in contact in a saved World are only in contact up to rounding error.
+const TOLERANCE: f32 = 1e-4;
+
+
+impl Scene {
+    pub fn load(path: &str) -> Result<Scene, SceneError> {
+        let text = fs::read_to_string(path).map_err(SceneError::Io)?;
+        Scene::parse(&text)
+    }
+
+
+    pub fn parse(text: &str) -> Result<Scene, SceneError> {
+        ron::from_str(text).map_err(|e| SceneError::Parse(e.to_string()))
+    }
+
+
+    pub fn save(&self, path: &str) -> Result<(), SceneError> {
+        fs::write(path, self.to_ron()).map_err(SceneError::Io)
+    }
+
+
+    pub fn to_ron(&self) -> String {
+        ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .expect("a scene is always serializable")
+    }
+
+
+    // The current state of a world, with every ball listed individually. The seed is kept as a record
+    // of where the world came from, though with no spawn regions it has no effect on loading.
+    // A world whose walls start with those of a box is saved as the box, with its other walls as
+    // baffles. A periodic world is always saved as a box around its cell, so the walls of its box on
+    // any axis that isn't periodic must all have the same restitution, as built.
+    pub fn from_world(world: &World) -> Scene {
+        let walls = world.get_walls();
+        let periodic = world.get_periodic();
+        // The walls of a box share their restitution, so it is taken from the first
+        let restitution = walls.first().map_or(1.0, Wall::restitution);
+        let (container, baffles) = if periodic.is_periodic() {
+            let box_walls = box_walls(&periodic.cell, (periodic.x, periodic.y), restitution);
+            let baffles = walls.iter().filter(|wall| !box_walls.contains(wall)).copied().collect();
+            (Container::Box(periodic.cell, restitution), baffles)
+        } else {
+            match world.bounding_rect() {
+                Some(rect) if walls.starts_with(&box_walls(&rect, (false, false), restitution)) => {
+                    (Container::Box(rect, restitution), walls[4..].to_vec())
+                },
+                _ => (Container::Walls(walls.to_vec()), Vec::new()),
+            }
+        };
+        let balls = world.get_balls().iter()
+            .map(|b| BallSpec{position: b.get_position(), velocity: b.velocity, radius: b.circle.radius,
+                              mass: b.get_mass(), angular_velocity: b.angular_velocity, restitution: b.get_restitution(),
+                              friction: b.get_friction(), inertia_factor: b.get_inertia_factor()})
+            .collect();
+        Scene{seed: world.get_seed(), container, baffles, obstacles: world.get_obstacles().to_vec(),
+              lattices: Vec::new(), balls, spawn: Vec::new(),
+              restitution_rule: world.get_restitution_rule(), collapse_guard: world.get_collapse_guard(),
+              friction_rule: world.get_friction_rule(), tangential_restitution: world.get_tangential_restitution(),
+              field: world.get_field(), integrator: world.get_integrator(), periodic: (periodic.x, periodic.y)}
+    }
+
+
+    // Validates the scene and builds it. Spawned balls come after the listed ones, in the order of their regions.
+    pub fn build(&self) -> Result<World, SceneError> {
+        let walls = self.walls()?;
+        let mut world = World::empty();
+        for wall in &walls {
+            world.add_wall(*wall);
+        }
+        world.set_restitution_rule(self.restitution_rule);
+        world.set_collapse_guard(self.collapse_guard);
+        world.set_friction_rule(self.friction_rule);
+        if !(-1.0..=1.0).contains(&self.tangential_restitution) {
+            return Err(SceneError::InvalidTangentialRestitution);
+        }
+        world.set_tangential_restitution(self.tangential_restitution);
+        world.set_field(self.field);
+        check_integrator(&self.integrator)?;
+        if let Integrator::Exact{..} = self.integrator {
+            if walls.iter().any(|w| !w.motion().is_fixed()) {
+                return Err(SceneError::InvalidIntegrator("walls cannot move with the exact integrator".to_string()));
+            }
+        }
+        world.set_integrator(self.integrator);
+        let periodic = self.periodic()?;
+        world.set_periodic(periodic);
+
+        let obstacles = self.obstacles()?;
+        for obstacle in &obstacles {
+            world.add_obstacle(*obstacle);
+        }
+        let space = Space::new(&walls, &self.container, &obstacles, periodic);
+
+        // Every ball placed so far, for overlap checks
+        let mut placed: Vec<(Ball, Entry)> = Vec::new();
+
+        for (i, spec) in self.balls.iter().enumerate() {
+            let entry = Entry::Ball(i);
+            let ball = spec.to_ball();
+            check_ball(&ball, entry)?;
+            if !space.inside_walls(&ball) {
+                return Err(SceneError::OutsideWalls(entry));
+            }
+            if !space.clear_of_obstacles(&ball) {
+                return Err(SceneError::OnObstacle(entry));
+            }
+            placed.push((ball, entry));
+        }
+
+        let max_radius = self.balls.iter().map(|b| b.radius)
+            .chain(self.spawn.iter().map(|s| s.radius))
+            .fold(0.0, f32::max);
+        let mut grid = SpatialGrid::with_periodic((2.0 * max_radius).max(1.0), periodic);
+        // Soft balls may overlap
+        let hard = self.integrator == Integrator::EventDriven;
+        for (i, (ball, entry)) in placed.iter().enumerate() {
+            let (min, max) = SpatialGrid::swept_bounds(ball, Vector2f::default(), 0.0);
+            if hard {
+                if let Some(&j) = grid.query(min, max).iter().find(|&&j| overlaps(&placed[j].0, ball, &periodic)) {
+                    return Err(SceneError::Overlap(*entry, placed[j].1));
+                }
+            }
+            grid.insert(i, min, max);
+        }
+
+        let seed = self.seed.unwrap_or_else(World::random_seed);
+        world.set_seed(Some(seed));
+        let mut rng = StdRng::seed_from_u64(seed);
+        for (i, region) in self.spawn.iter().enumerate() {
+            check_spawn(region, i)?;
+            for _ in 0..region.count {
+                let ball = spawn_ball(region, &space, &placed, &grid, &mut rng)
+                    .ok_or_else(|| SceneError::InvalidSpawn(i, String::from("could not find room for every ball")))?;
+                let (min, max) = SpatialGrid::swept_bounds(&ball, Vector2f::default(), 0.0);
+                grid.insert(placed.len(), min, max);
+                placed.push((ball, Entry::Spawn(i)));
+            }
+        }
+
+        for (ball, _) in placed {
+            world.add_ball(ball);
+        }
+        Ok(world)
+    }
+
+
+    fn periodic(&self) -> Result<Periodic, SceneError> {
+        match (&self.container, self.periodic) {
+            (_, (false, false)) => Ok(Periodic::default()),
+            (Container::Box(rect, _), (x, y)) => Ok(Periodic::new(*rect, x, y)),
+            _ => Err(SceneError::InvalidContainer(String::from("only a box can be periodic"))),
+        }
+    }
+
+
+    // The listed obstacles followed by those of each lattice in turn
+    fn obstacles(&self) -> Result<Vec<Obstacle>, SceneError> {
+        let check = |obstacle: &Obstacle, entry: Entry| {
+            let invalid = |reason: &str| Err(SceneError::InvalidObstacle(entry, String::from(reason)));
+            if !is_positive(obstacle.radius) {
+                return invalid("radius must be positive");
+            }
+            if !(0.0..=1.0).contains(&obstacle.restitution) {
+                return invalid("restitution must be between 0 and 1");
+            }
+            if !(is_positive(obstacle.friction) || obstacle.friction == 0.0) {
+                return invalid("friction must not be negative");
+            }
+            Ok(())
+        };
+
+        let mut obstacles = Vec::new();
+        for (i, obstacle) in self.obstacles.iter().enumerate() {
+            check(obstacle, Entry::Obstacle(i))?;
+            obstacles.push(*obstacle);
+        }
+        for (i, lattice) in self.lattices.iter().enumerate() {
+            let template = Obstacle{restitution: lattice.restitution, friction: lattice.friction,
+                                    ..Obstacle::new(lattice.origin, lattice.radius)};
+            check(&template, Entry::Lattice(i))?;
+            for x in 0..lattice.counts.0 {
+                for y in 0..lattice.counts.1 {
+                    let position = lattice.origin + lattice.a * x as f32 + lattice.b * y as f32;
+                    obstacles.push(Obstacle{position, ..template});
+                }
+            }
+        }
+        Ok(obstacles)
+    }
+
+
+    fn walls(&self) -> Result<Vec<Wall>, SceneError> {
+        let mut walls: Vec<Wall> = match &self.container {
+            Container::Box(rect, restitution) => {
+                if !(rect.width > 0.0 && rect.height > 0.0) {
+                    return Err(SceneError::InvalidContainer(String::from("box must have positive width and height")));
+                }
+                box_walls(rect, self.periodic, *restitution)
+            },
+            Container::Polygon(vertices) => {
+                if vertices.len() < 3 {
+                    return Err(SceneError::InvalidContainer(String::from("polygon must have at least 3 vertices")));
+                }
+                Segment::polygon(vertices).into_iter().map(Wall::from).collect()
+            },
+            Container::Circle{centre, radius} => vec![Wall::from(CircularArc::circle(*centre, *radius))],
+            Container::Stadium{centre, length, radius} => {
+                if !is_positive(*length) {
+                    return Err(SceneError::InvalidContainer(String::from("stadium must have a positive length")));
+                }
+                let half = Vector2f{x: 0.5 * length, y: 0.0};
+                let up = Vector2f{x: 0.0, y: *radius};
+                let quarter = 0.5 * std::f32::consts::PI;
+                let arc = |centre: Vector2f, start_angle: f32| CircularArc{start_angle, sweep: 2.0 * quarter, ..CircularArc::circle(centre, *radius)};
+                vec![Wall::from(Segment::new(*centre - half - up, *centre + half - up)),
+                     Wall::from(arc(*centre + half, -quarter)),
+                     Wall::from(Segment::new(*centre + half + up, *centre - half + up)),
+                     Wall::from(arc(*centre - half, quarter))]
+            },
+            Container::Walls(walls) => walls.clone(),
+        };
+        walls.extend_from_slice(&self.baffles);
+
+        for (i, wall) in walls.iter_mut().enumerate() {
+            match wall {
+                Wall::Plane(plane) => {
+                    let length = vector_math::length_squared(&plane.normal).sqrt();
+                    if !is_positive(length) {
+                        return Err(SceneError::InvalidContainer(format!("wall {} has a zero normal", i)));
+                    }
+                    plane.normal /= length;
+                    if plane.motion.amplitude != Vector2f::default() && !is_positive(plane.motion.period) {
+                        return Err(SceneError::InvalidContainer(format!("wall {} must oscillate with a positive period", i)));
+                    }
+                },
+                Wall::Segment(segment) => {
+                    if !is_positive(segment.length()) {
+                        return Err(SceneError::InvalidContainer(format!("wall {} has no length", i)));
+                    }
+                },
+                Wall::Arc(arc) => {
+                    if !(is_positive(arc.radius) && is_positive(arc.sweep)) {
+                        return Err(SceneError::InvalidContainer(format!("wall {} must have a positive radius and sweep", i)));
+                    }
+                },
+            }
+            if !(0.0..=1.0).contains(&wall.restitution()) {
+                return Err(SceneError::InvalidContainer(format!("wall {} has restitution outside [0, 1]", i)));
+            }
+            if !(is_positive(wall.friction()) || wall.friction() == 0.0) {
+                return Err(SceneError::InvalidContainer(format!("wall {} has negative friction", i)));
+            }
+            if let Some(temperature) = wall.temperature() {
+                if !(is_positive(temperature) || temperature == 0.0) {
+                    return Err(SceneError::InvalidContainer(format!("wall {} has a negative temperature", i)));
+                }
+            }
+        }
+        Ok(walls)
+    }
+}
+
+
+impl BallSpec {
+    pub fn to_ball(&self) -> Ball {
+        let mut ball = Ball::new(self.position, self.velocity, self.radius, self.mass);
+        ball.angular_velocity = self.angular_velocity;
+        ball.set_restitution(self.restitution);
+        ball.set_friction(self.friction);
+        ball.set_inertia_factor(self.inertia_factor);
+        ball
+    }
+}
+
+
+impl SpawnRegion {
+    // One of the balls this region spawns
+    pub fn ball_at(&self, position: Vector2f, velocity: Vector2f) -> Ball {
+        let mut ball = Ball::new(position, velocity, self.radius, self.mass);
+        ball.set_restitution(self.restitution);
+        ball.set_friction(self.friction);
+        ball.set_inertia_factor(self.inertia_factor);
+        ball
+    }
+}
+
+
+// The walls of a box, leaving out those on periodic axes
+fn box_walls(rect: &Rect, periodic: (bool, bool), restitution: f32) -> Vec<Wall> {
+    let [left, right, top, bottom] = World::rect_to_planes(rect).map(|plane| Plane{restitution, ..plane});
+    let mut walls = Vec::new();
+    if !periodic.0 {
+        walls.extend([Wall::from(left), Wall::from(right)]);
+    }
+    if !periodic.1 {
+        walls.extend([Wall::from(top), Wall::from(bottom)]);
+    }
+    walls
+}
+
+
+fn check_integrator(integrator: &Integrator) -> Result<(), SceneError> {
+    let potential = match integrator {
+        Integrator::EventDriven => return Ok(()),
+        Integrator::Verlet{potential, max_step} => {
+            if !is_positive(*max_step) {
+                return Err(SceneError::InvalidIntegrator("max_step must be positive".to_string()));
+            }
+            potential
+        },
+        Integrator::Exact{potential, step, resolution} => {
+            if !is_positive(*step) || !is_positive(*resolution) {
+                return Err(SceneError::InvalidIntegrator("step and resolution must be positive".to_string()));
+            }
+            potential
+        },
+    };
+    let valid = match *potential {
+        Potential::LennardJones{epsilon, cutoff} => is_positive(epsilon) && cutoff > 1.0,
+        Potential::Wca{epsilon} => is_positive(epsilon),
+        Potential::Harmonic{stiffness} => is_positive(stiffness),
+    };
+    if !valid {
+        return Err(SceneError::InvalidIntegrator(format!("invalid potential {:?}", potential)));
+    }
+    Ok(())
+}
+
+
+fn check_ball(ball: &Ball, entry: Entry) -> Result<(), SceneError> {
+    if !is_positive(ball.get_mass()) {
+        return Err(SceneError::NonPositiveMass(entry));
+    }
+    if !is_positive(ball.circle.radius) {
+        return Err(SceneError::NonPositiveRadius(entry));
+    }
+    if !(0.0..=1.0).contains(&ball.get_restitution()) {
+        return Err(SceneError::InvalidRestitution(entry));
+    }
+    if !(is_positive(ball.get_friction()) || ball.get_friction() == 0.0) {
+        return Err(SceneError::NegativeFriction(entry));
+    }
+    if !is_positive(ball.get_inertia_factor()) {
+        return Err(SceneError::NonPositiveInertia(entry));
+    }
+    Ok(())
+}
+
+
+fn check_spawn(region: &SpawnRegion, index: usize) -> Result<(), SceneError> {
+    check_ball(&region.ball_at(Vector2f::default(), Vector2f::default()), Entry::Spawn(index))?;
+    let invalid = |reason: &str| Err(SceneError::InvalidSpawn(index, String::from(reason)));
+    if region.region.width < 2.0 * region.radius || region.region.height < 2.0 * region.radius {
+        return invalid("region is too small to hold a ball");
+    }
+    match region.speed {
+        Speed::Fixed(speed) if !(is_positive(speed) || speed == 0.0) => invalid("speed must be non-negative"),
+        Speed::Uniform{min, max} if !(0.0 <= min && min <= max) => invalid("speeds must satisfy 0 <= min <= max"),
+        Speed::MaxwellBoltzmann{temperature} if !(is_positive(temperature) || temperature == 0.0) => invalid("temperature must be non-negative"),
+        _ => Ok(()),
+    }
+}
+
+
+// False for NaN as well as for zero and negative numbers
+fn is_positive(x: f32) -> bool {
+    x > 0.0
+}
+
+
+impl Container {
+    // Whether point is in the space the container encloses, not counting the walls themselves
+    fn encloses(&self, point: Vector2f) -> bool {
+        match self {
+            Container::Polygon(vertices) => inside_polygon(point, vertices),
+            Container::Circle{centre, radius} => vector_math::length_squared(&(point - *centre)) < radius * radius,
+            Container::Stadium{centre, length, radius} => {
+                let half = Vector2f{x: 0.5 * length, y: 0.0};
+                let axis = Segment::new(*centre - half, *centre + half);
+                vector_math::length_squared(&(point - axis.closest_point(point))) < radius * radius
+            },
+            _ => true,
+        }
+    }
+}
+
+
+// Where balls may be placed
+struct Space<'a> {
+    walls: &'a [Wall],
+    container: &'a Container,
+    obstacles: &'a [Obstacle],
+    obstacle_grid: SpatialGrid,
+    periodic: Periodic,
+}
+
+
+impl<'a> Space<'a> {
+    fn new(walls: &'a [Wall], container: &'a Container, obstacles: &'a [Obstacle], periodic: Periodic) -> Space<'a> {
+        let balls: Vec<Ball> = obstacles.iter().map(Obstacle::as_ball).collect();
+        let obstacle_grid = SpatialGrid::from_balls(&balls, &Field::None, &periodic, 0.0, None);
+        Space{walls, container, obstacles, obstacle_grid, periodic}
+    }
+
+
+    // On a periodic axis, the ball's centre must be in the cell
+    fn inside_walls(&self, ball: &Ball) -> bool {
+        let p = ball.get_position();
+        self.periodic.wrap(p) == p && self.container.encloses(p) &&
+            self.walls.iter().all(|wall| wall.distance(p) >= ball.circle.radius * (1.0 - TOLERANCE))
+    }
+
+
+    fn clear_of_obstacles(&self, ball: &Ball) -> bool {
+        let (min, max) = SpatialGrid::swept_bounds(ball, Vector2f::default(), 0.0);
+        self.obstacle_grid.query(min, max).iter()
+            .all(|&i| {
+                let obstacle = &self.obstacles[i];
+                obstacle.distance(self.periodic.nearest_image(ball.get_position(), obstacle.position)) >= ball.circle.radius * (1.0 - TOLERANCE)
+            })
+    }
+}
+
+
+// Even-odd rule: a ray from the point crosses the edges an odd number of times if it is inside
+fn inside_polygon(point: Vector2f, vertices: &[Vector2f]) -> bool {
+    let mut inside = false;
+    for i in 0..vertices.len() {
+        let a = vertices[i];
+        let b = vertices[(i + 1) % vertices.len()];
+        if (a.y > point.y) != (b.y > point.y) && point.x < a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+
+fn overlaps(a: &Ball, b: &Ball, periodic: &Periodic) -> bool {
+    let contact = (a.circle.radius + b.circle.radius) * (1.0 - TOLERANCE);
+    vector_math::length_squared(&periodic.minimum_image(a.get_position() - b.get_position())) < contact * contact
+}
+
+
+// A ball at a random free position in the region, or None if no room was found
+fn spawn_ball<R: Rng>(region: &SpawnRegion, space: &Space, placed: &[(Ball, Entry)],
+                      grid: &SpatialGrid, rng: &mut R) -> Option<Ball> {
+    let r = region.radius;
+    let area = &region.region;
+    for _ in 0..MAX_SPAWN_ATTEMPTS {
+        let position = Vector2f{x: rng.gen_range(area.left + r, area.left + area.width - r),
+                                y: rng.gen_range(area.top + r, area.top + area.height - r)};
+        let velocity = random_velocity(&region.speed, region.mass, rng);
+        let ball = region.ball_at(position, velocity);
+
+        let (min, max) = SpatialGrid::swept_bounds(&ball, Vector2f::default(), 0.0);
+        if space.inside_walls(&ball) && space.clear_of_obstacles(&ball) && !grid.query(min, max).iter().any(|&j| overlaps(&placed[j].0, &ball, &space.periodic)) {
+            return Some(ball);
+        }
+    }
+    None
+}
+
+
+pub fn random_velocity<R: Rng>(speed: &Speed, mass: f32, rng: &mut R) -> Vector2f {
+    match *speed {
+        Speed::MaxwellBoltzmann{temperature} => {
+            let sigma = (temperature / mass).sqrt();
+            Vector2f{x: sigma * standard_normal(rng), y: sigma * standard_normal(rng)}
+        },
+        Speed::Fixed(speed) => random_direction(rng) * speed,
+        Speed::Uniform{min, max} => {
+            let speed = if min < max { rng.gen_range(min, max) } else { min };
+            random_direction(rng) * speed
+        }
+    }
+}
+
+
+fn random_direction<R: Rng>(rng: &mut R) -> Vector2f {
+    let angle = rng.gen_range(0.0, 2.0 * std::f32::consts::PI);
+    vector_math::rotate(&Vector2f{x: 1.0, y: 0.0}, angle)
+}
+
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Entry::Container => write!(f, "container"),
+            Entry::Ball(i)   => write!(f, "balls[{}]", i),
+            Entry::Spawn(i)  => write!(f, "spawn[{}]", i),
+            Entry::Obstacle(i) => write!(f, "obstacles[{}]", i),
+            Entry::Lattice(i) => write!(f, "lattices[{}]", i),
+        }
+    }
+}
+
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(e)                   => write!(f, "{}", e),
+            SceneError::Parse(e)                => write!(f, "parse error: {}", e),
+            SceneError::NonPositiveMass(e)      => write!(f, "{}: mass must be positive", e),
+            SceneError::NonPositiveRadius(e)    => write!(f, "{}: radius must be positive", e),
+            SceneError::InvalidRestitution(e)   => write!(f, "{}: restitution must be between 0 and 1", e),
+            SceneError::NegativeFriction(e)     => write!(f, "{}: friction must not be negative", e),
+            SceneError::NonPositiveInertia(e)   => write!(f, "{}: inertia factor must be positive", e),
+            SceneError::InvalidTangentialRestitution => write!(f, "tangential restitution must be between -1 and 1"),
+            SceneError::InvalidIntegrator(reason) => write!(f, "integrator: {}", reason),
+            SceneError::OutsideWalls(e)         => write!(f, "{}: ball is not inside the walls", e),
+            SceneError::OnObstacle(e)           => write!(f, "{}: ball overlaps an obstacle", e),
+            SceneError::InvalidObstacle(e, r)   => write!(f, "{}: {}", e, r),
+            SceneError::Overlap(a, b)           => write!(f, "{}: overlaps {}", a, b),
+            SceneError::InvalidSpawn(i, reason) => write!(f, "{}: {}", Entry::Spawn(*i), reason),
+            SceneError::InvalidContainer(r)     => write!(f, "{}: {}", Entry::Container, r),
+        }
+    }
+}
+
+
+impl std::error::Error for SceneError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An empty 100 by 100 box
+    fn box_scene() -> Scene {
+        Scene::parse("(container: Box((left: 0.0, top: 0.0, width: 100.0, height: 100.0)))").unwrap()
+    }
+
+
+    fn ball(x: f32, y: f32, radius: f32) -> BallSpec {
+        BallSpec{position: Vector2f{x, y}, velocity: Vector2f{x: 10.0, y: -5.0}, radius, mass: 1.0, angular_velocity: 0.0,
+                 restitution: 1.0, friction: 0.0, inertia_factor: default_inertia_factor()}
+    }
+
+
+    fn spawn(count: usize) -> SpawnRegion {
+        SpawnRegion{count, region: Rect{left: 0.0, top: 0.0, width: 100.0, height: 100.0}, radius: 2.0, mass: 1.0,
+                    restitution: 1.0, friction: 0.0, inertia_factor: default_inertia_factor(), speed: Speed::Fixed(10.0)}
+    }
+
+
+    // box_scene with three valid balls, the third changed by change
+    fn with_third_ball<F: Fn(&mut BallSpec)>(change: F) -> Result<World, SceneError> {
+        let mut scene = box_scene();
+        scene.balls = vec![ball(20.0, 20.0, 5.0), ball(50.0, 50.0, 5.0), ball(80.0, 80.0, 5.0)];
+        change(&mut scene.balls[2]);
+        scene.build()
+    }
+
+
+    #[test]
+    fn io_error() {
+        assert!(matches!(Scene::load("/nonexistent/scene.ron"), Err(SceneError::Io(_))));
+    }
+
+
+    #[test]
+    fn parse_error() {
+        assert!(matches!(Scene::parse("(container: Box(("), Err(SceneError::Parse(_))));
+        assert!(matches!(Scene::parse("(balls: [])"), Err(SceneError::Parse(_))));
+    }
+
+
+    #[test]
+    fn non_positive_mass() {
+        assert!(matches!(with_third_ball(|b| b.mass = 0.0), Err(SceneError::NonPositiveMass(Entry::Ball(2)))));
+        assert!(matches!(with_third_ball(|b| b.mass = f32::NAN), Err(SceneError::NonPositiveMass(Entry::Ball(2)))));
+    }
+
+
+    #[test]
+    fn non_positive_radius() {
+        assert!(matches!(with_third_ball(|b| b.radius = -1.0), Err(SceneError::NonPositiveRadius(Entry::Ball(2)))));
+    }
+
+
+    #[test]
+    fn invalid_restitution() {
+        assert!(matches!(with_third_ball(|b| b.restitution = 1.5), Err(SceneError::InvalidRestitution(Entry::Ball(2)))));
+    }
+
+
+    #[test]
+    fn negative_friction() {
+        assert!(matches!(with_third_ball(|b| b.friction = -0.1), Err(SceneError::NegativeFriction(Entry::Ball(2)))));
+    }
+
+
+    #[test]
+    fn non_positive_inertia() {
+        assert!(matches!(with_third_ball(|b| b.inertia_factor = 0.0), Err(SceneError::NonPositiveInertia(Entry::Ball(2)))));
+    }
+
+
+    #[test]
+    fn outside_walls() {
+        assert!(matches!(with_third_ball(|b| b.position.x = 97.0), Err(SceneError::OutsideWalls(Entry::Ball(2)))));
+        assert!(matches!(with_third_ball(|b| b.position.y = 150.0), Err(SceneError::OutsideWalls(Entry::Ball(2)))));
+
+        let mut circle = box_scene();
+        circle.container = Container::Circle{centre: Vector2f{x: 0.0, y: 0.0}, radius: 50.0};
+        circle.balls = vec![ball(0.0, 0.0, 5.0), ball(40.0, 40.0, 5.0)];
+        assert!(matches!(circle.build(), Err(SceneError::OutsideWalls(Entry::Ball(1)))));
+    }
+
+
+    #[test]
+    fn on_obstacle() {
+        let mut scene = box_scene();
+        scene.obstacles = vec![Obstacle::new(Vector2f{x: 50.0, y: 20.0}, 10.0)];
+        scene.balls = vec![ball(20.0, 20.0, 5.0), ball(50.0, 34.0, 5.0)];
+        assert!(matches!(scene.build(), Err(SceneError::OnObstacle(Entry::Ball(1)))));
+    }
+
+
+    #[test]
+    fn invalid_obstacle() {
+        let mut scene = box_scene();
+        scene.obstacles = vec![Obstacle::new(Vector2f{x: 50.0, y: 20.0}, 10.0), Obstacle::new(Vector2f{x: 50.0, y: 70.0}, 0.0)];
+        assert!(matches!(scene.build(), Err(SceneError::InvalidObstacle(Entry::Obstacle(1), _))));
+
+        let mut scene = box_scene();
+        scene.lattices = vec![Lattice{origin: Vector2f{x: 10.0, y: 10.0}, a: Vector2f{x: 20.0, y: 0.0}, b: Vector2f{x: 0.0, y: 20.0},
+                                      counts: (4, 4), radius: 2.0, restitution: 2.0, friction: 0.0}];
+        assert!(matches!(scene.build(), Err(SceneError::InvalidObstacle(Entry::Lattice(0), _))));
+    }
+
+
+    #[test]
+    fn overlap() {
+        match with_third_ball(|b| b.position = Vector2f{x: 56.0, y: 52.0}) {
+            Err(SceneError::Overlap(a, b)) => assert!((a, b) == (Entry::Ball(2), Entry::Ball(1)) || (a, b) == (Entry::Ball(1), Entry::Ball(2))),
+            other => panic!("{:?}", other.map(|_| ())),
+        }
+        // Touching is not overlapping
+        assert!(with_third_ball(|b| b.position = Vector2f{x: 60.0, y: 50.0}).is_ok());
+    }
+
+
+    #[test]
+    fn invalid_spawn() {
+        let mut scene = box_scene();
+        scene.spawn = vec![spawn(10), SpawnRegion{speed: Speed::Uniform{min: 5.0, max: 1.0}, ..spawn(10)}];
+        assert!(matches!(scene.build(), Err(SceneError::InvalidSpawn(1, _))));
+
+        // More balls than fit in the box
+        scene.spawn = vec![spawn(10), spawn(1000)];
+        assert!(matches!(scene.build(), Err(SceneError::InvalidSpawn(1, _))));
+
+        scene.spawn = vec![SpawnRegion{mass: -1.0, ..spawn(10)}];
+        assert!(matches!(scene.build(), Err(SceneError::NonPositiveMass(Entry::Spawn(0)))));
+    }
+
+
+    #[test]
+    fn invalid_container() {
+        let mut scene = box_scene();
+        scene.container = Container::Polygon(vec![Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 1.0, y: 0.0}]);
+        assert!(matches!(scene.build(), Err(SceneError::InvalidContainer(_))));
+        scene.container = Container::Circle{centre: Vector2f{x: 0.0, y: 0.0}, radius: 50.0};
+        scene.periodic = (true, false);
+        assert!(matches!(scene.build(), Err(SceneError::InvalidContainer(_))));
+    }
+
+
+    #[test]
+    fn invalid_tangential_restitution() {
+        let mut scene = box_scene();
+        scene.tangential_restitution = 1.5;
+        assert!(matches!(scene.build(), Err(SceneError::InvalidTangentialRestitution)));
+    }
+
+
+    #[test]
+    fn invalid_integrator() {
+        let mut scene = box_scene();
+        scene.integrator = Integrator::Verlet{potential: Potential::Wca{epsilon: 1.0}, max_step: 0.0};
+        assert!(matches!(scene.build(), Err(SceneError::InvalidIntegrator(_))));
+    }
+
+
+    #[test]
+    fn ron_round_trip() {
+        let mut scene = box_scene();
+        scene.seed = Some(42);
+        scene.balls = vec![ball(20.0, 20.0, 5.0), ball(50.0, 50.0, 8.0)];
+        scene.spawn = vec![SpawnRegion{speed: Speed::MaxwellBoltzmann{temperature: 100.0}, ..spawn(20)}];
+        scene.field = Field::Gravity(Vector2f{x: 0.0, y: 9.8});
+
+        let text = scene.to_ron();
+        let parsed = Scene::parse(&text).unwrap();
+        assert_eq!(parsed.to_ron(), text);
+
+        let (a, b) = (scene.build().unwrap(), parsed.build().unwrap());
+        assert_eq!(a.get_balls().len(), 22);
+        for (x, y) in a.get_balls().iter().zip(b.get_balls()) {
+            assert_eq!((x.get_position(), x.velocity), (y.get_position(), y.velocity));
+        }
+    }
+
+
+    #[test]
+    fn box_wall_restitution_is_saved() {
+        for &periodic in &[(false, false), (true, false)] {
+            let mut scene = box_scene();
+            scene.container = Container::Box(Rect{left: 0.0, top: 0.0, width: 100.0, height: 100.0}, 0.5);
+            scene.periodic = periodic;
+            scene.baffles = vec![Wall::from(Segment::new(Vector2f{x: 10.0, y: 50.0}, Vector2f{x: 90.0, y: 50.0}))];
+            let world = scene.build().unwrap();
+            assert!(world.get_walls()[0].restitution() == 0.5);
+
+            let saved = Scene::from_world(&world);
+            assert!(matches!(saved.container, Container::Box(_, r) if r == 0.5));
+            let loaded = Scene::parse(&saved.to_ron()).unwrap().build().unwrap();
+            assert_eq!(loaded.get_walls(), world.get_walls());
+        }
+
+        // Walls with different restitutions can't be described as a box
+        let mut world = box_scene().build().unwrap();
+        let mut wall = world.get_walls()[1];
+        if let Wall::Plane(plane) = &mut wall {
+            plane.restitution = 0.25;
+        }
+        world.set_wall(1, wall);
+        let saved = Scene::from_world(&world);
+        assert!(matches!(saved.container, Container::Walls(_)));
+        assert_eq!(saved.build().unwrap().get_walls(), world.get_walls());
+    }
+
+
+    #[test]
+    fn saved_world_loads_the_same() {
+        let mut scene = box_scene();
+        scene.seed = Some(7);
+        scene.spawn = vec![spawn(30)];
+        let mut world = scene.build().unwrap();
+        world.update(0.5);
+
+        let path = std::env::temp_dir().join(format!("scene_round_trip_{}.ron", std::process::id()));
+        let path = path.to_str().unwrap();
+        Scene::from_world(&world).save(path).unwrap();
+        let loaded = Scene::load(path).unwrap().build().unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.get_seed(), Some(7));
+        assert_eq!(loaded.get_walls(), world.get_walls());
+        for (x, y) in world.get_balls().iter().zip(loaded.get_balls()) {
+            assert_eq!((x.get_position(), x.velocity, x.get_mass()), (y.get_position(), y.velocity, y.get_mass()));
+        }
+    }
+
+
+    #[test]
+    fn lattice_generates_obstacles() {
+        let mut scene = box_scene();
+        scene.obstacles = vec![Obstacle::new(Vector2f{x: 5.0, y: 5.0}, 1.0)];
+        scene.lattices = vec![Lattice{origin: Vector2f{x: 20.0, y: 20.0}, a: Vector2f{x: 20.0, y: 0.0}, b: Vector2f{x: 10.0, y: 20.0},
+                                      counts: (3, 2), radius: 4.0, restitution: 0.5, friction: 0.25}];
+        let world = scene.build().unwrap();
+        let obstacles = world.get_obstacles();
+        assert_eq!(obstacles.len(), 7);
+        assert_eq!(obstacles[0].position, Vector2f{x: 5.0, y: 5.0});
+        let positions: Vec<(f32, f32)> = obstacles[1..].iter().map(|o| (o.position.x, o.position.y)).collect();
+        assert_eq!(positions, vec![(20.0, 20.0), (30.0, 40.0), (40.0, 20.0), (50.0, 40.0), (60.0, 20.0), (70.0, 40.0)]);
+        assert!(obstacles[1..].iter().all(|o| o.radius == 4.0 && o.restitution == 0.5 && o.friction == 0.25));
+
+        // A ball may not be placed on one of them
+        scene.balls = vec![ball(50.0, 45.0, 2.0)];
+        assert!(matches!(scene.build(), Err(SceneError::OnObstacle(Entry::Ball(0)))));
+    }
+}
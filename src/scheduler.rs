@@ -0,0 +1,130 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use super::world::{Collision, CollisionKind};
+
+
+// A predicted collision, stamped with the collision counters of the balls involved at the
+// time it was predicted. If either ball has collided since, the prediction is stale.
+#[derive(Copy, Clone, Debug)]
+pub struct Event {
+    // Time since the start of the step, always positive regardless of the direction of time
+    pub elapsed: f32,
+    pub collision: Collision,
+    pub ball_count: u64,
+    pub collider_count: u64,
+}
+
+
+impl Event {
+    pub fn is_valid(&self, collision_counts: &[u64]) -> bool {
+        if collision_counts[self.collision.ball_index] != self.ball_count {
+            return false;
+        }
+        match self.collision.kind {
+            CollisionKind::Ball => collision_counts[self.collision.collider_index] == self.collider_count,
+            CollisionKind::Wall | CollisionKind::Obstacle => true,
+        }
+    }
+
+
+    // Events are ordered by time, with ties broken by the indices involved so that
+    // simultaneous collisions are always processed in the same order
+    fn key(&self) -> (usize, usize, usize) {
+        let kind = match self.collision.kind {
+            CollisionKind::Ball => 0,
+            CollisionKind::Wall => 1,
+            CollisionKind::Obstacle => 2,
+        };
+        (self.collision.ball_index, kind, self.collision.collider_index)
+    }
+}
+
+
+impl PartialEq for Event {
+    fn eq(&self, other: &Event) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+
+impl Eq for Event {}
+
+
+impl PartialOrd for Event {
+    fn partial_cmp(&self, other: &Event) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+
+impl Ord for Event {
+    // Reversed, so that the BinaryHeap (a max-heap) yields the soonest event first
+    fn cmp(&self, other: &Event) -> Ordering {
+        other.elapsed.total_cmp(&self.elapsed)
+            .then_with(|| other.key().cmp(&self.key()))
+    }
+}
+
+
+// Predicted collisions, soonest first
+pub struct EventQueue {
+    heap: BinaryHeap<Event>,
+}
+
+
+impl EventQueue {
+    pub fn new() -> EventQueue {
+        EventQueue{heap: BinaryHeap::new()}
+    }
+
+
+    pub fn push(&mut self, event: Event) {
+        self.heap.push(event);
+    }
+
+
+    // Removes and returns the soonest event that is still valid, discarding stale ones
+    pub fn pop_valid(&mut self, collision_counts: &[u64]) -> Option<Event> {
+        while let Some(event) = self.heap.pop() {
+            if event.is_valid(collision_counts) {
+                return Some(event);
+            }
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(elapsed: f32, kind: CollisionKind, ball_index: usize, collider_index: usize, counts: &[u64]) -> Event {
+        let collider_count = if kind == CollisionKind::Ball { counts[collider_index] } else { 0 };
+        Event{elapsed, collision: Collision{kind, time: elapsed, ball_index, collider_index},
+              ball_count: counts[ball_index], collider_count}
+    }
+
+
+    #[test]
+    fn stale_events_are_dropped() {
+        let mut counts = vec![0, 0, 0, 0];
+        let mut queue = EventQueue::new();
+        queue.push(event(0.3, CollisionKind::Ball, 0, 1, &counts));
+        queue.push(event(0.1, CollisionKind::Ball, 1, 2, &counts));
+        queue.push(event(0.2, CollisionKind::Wall, 2, 0, &counts));
+        queue.push(event(0.4, CollisionKind::Wall, 3, 1, &counts));
+
+        let first = queue.pop_valid(&counts).unwrap();
+        assert_eq!((first.collision.ball_index, first.collision.collider_index), (1, 2));
+        // Balls 1 and 2 collided, so every other prediction involving either is out of date
+        counts[1] += 1;
+        counts[2] += 1;
+        queue.push(event(0.5, CollisionKind::Ball, 1, 2, &counts));
+
+        let remaining: Vec<(usize, f32)> = std::iter::from_fn(|| queue.pop_valid(&counts))
+            .map(|e| (e.collision.ball_index, e.elapsed))
+            .collect();
+        assert_eq!(remaining, vec![(3, 0.4), (1, 0.5)]);
+    }
+}
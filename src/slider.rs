@@ -1,6 +1,5 @@
 use sfml::graphics::{RenderWindow, RenderTarget, Color, Transformable, Shape, RectangleShape, Font, Text, IntRect};
 use sfml::system::{Vector2f, Vector2i};
-use num;
 
 pub struct Slider {
     pub size: Vector2f,
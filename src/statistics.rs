@@ -0,0 +1,177 @@
+// Statistics of the balls' motion, for comparison with kinetic theory. Boltzmann's constant is taken
+// as 1, so temperature is measured in units of energy.
+use std::collections::VecDeque;
+use super::ball::Ball;
+use super::vector_math;
+
+
+// The mean translational kinetic energy per ball, which in two dimensions is the temperature
+pub fn temperature(balls: &[Ball]) -> f64 {
+    if balls.is_empty() {
+        return 0.0;
+    }
+    let energy: f64 = balls.iter()
+        .map(|b| 0.5 * b.get_mass() as f64 * vector_math::length_squared(&b.velocity) as f64)
+        .sum();
+    energy / balls.len() as f64
+}
+
+
+// The probability density of a speed under the two dimensional Maxwell-Boltzmann distribution for
+// balls of the given mass: (m v / T) exp(-m v^2 / 2T)
+pub fn maxwell_boltzmann(speed: f64, mass: f64, temperature: f64) -> f64 {
+    let a = mass / temperature;
+    a * speed * (-0.5 * a * speed * speed).exp()
+}
+
+
+// The pressures equations of state predict for n balls in the given area at the given temperature,
+// with packing_fraction the fraction of the area covered by the balls
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct EquationOfState {
+    // P = n T / A
+    pub ideal_gas: f64,
+    // Henderson's equation of state for hard disks of equal size: the ideal gas pressure times
+    // (1 + eta^2 / 8) / (1 - eta)^2, for packing fraction eta. Good up to about eta = 0.7.
+    pub hard_disks: f64,
+}
+
+
+impl EquationOfState {
+    pub fn predict(n: usize, area: f64, temperature: f64, packing_fraction: f64) -> EquationOfState {
+        let ideal_gas = n as f64 * temperature / area;
+        let eta = packing_fraction;
+        EquationOfState{ideal_gas, hard_disks: ideal_gas * (1.0 + eta * eta / 8.0) / (1.0 - eta).powi(2)}
+    }
+}
+
+
+// A histogram of ball speeds, averaged over the most recent frames
+pub struct SpeedHistogram {
+    bins: usize,
+    window: usize,
+    // The speed of every ball in each frame, with the temperature of the frame
+    frames: VecDeque<(Vec<f32>, f64)>,
+    // The masses of the balls in the latest frame, for the expected distribution
+    masses: Vec<f32>,
+}
+
+
+impl SpeedHistogram {
+    // window is the number of frames averaged over
+    pub fn new(bins: usize, window: usize) -> SpeedHistogram {
+        assert!(bins > 0 && window > 0, "a histogram needs at least one bin and one frame");
+        SpeedHistogram{bins, window, frames: VecDeque::new(), masses: Vec::new()}
+    }
+
+
+    pub fn record(&mut self, balls: &[Ball]) {
+        let speeds = balls.iter().map(|b| vector_math::length_squared(&b.velocity).sqrt()).collect();
+        self.frames.push_back((speeds, temperature(balls)));
+        while self.frames.len() > self.window {
+            self.frames.pop_front();
+        }
+        self.masses = balls.iter().map(Ball::get_mass).collect();
+    }
+
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+
+    pub fn get_bins(&self) -> usize {
+        self.bins
+    }
+
+
+    pub fn get_window(&self) -> usize {
+        self.window
+    }
+
+
+    // The mean over the window
+    pub fn temperature(&self) -> f64 {
+        if self.frames.is_empty() {
+            return 0.0;
+        }
+        self.frames.iter().map(|f| f.1).sum::<f64>() / self.frames.len() as f64
+    }
+
+
+    // The top of the last bin: four times the most probable speed of the lightest balls, beyond which
+    // very few are expected
+    pub fn max_speed(&self) -> f64 {
+        let lightest = self.masses.iter().copied().fold(f32::INFINITY, f32::min) as f64;
+        let max_speed = 4.0 * (self.temperature() / lightest).sqrt();
+        if max_speed.is_finite() && max_speed > 0.0 { max_speed } else { 1.0 }
+    }
+
+
+    pub fn bin_width(&self) -> f64 {
+        self.max_speed() / self.bins as f64
+    }
+
+
+    // The fraction of balls in each bin divided by the bin width, so that it can be compared with a
+    // probability density. Speeds beyond the last bin are counted but not shown.
+    pub fn densities(&self) -> Vec<f64> {
+        let mut counts = vec![0usize; self.bins];
+        let width = self.bin_width();
+        let mut total = 0;
+        for speed in self.frames.iter().flat_map(|f| f.0.iter()) {
+            let bin = (*speed as f64 / width) as usize;
+            if bin < self.bins {
+                counts[bin] += 1;
+            }
+            total += 1;
+        }
+        let scale = if total > 0 { 1.0 / (total as f64 * width) } else { 0.0 };
+        counts.iter().map(|&n| n as f64 * scale).collect()
+    }
+
+
+    // The Maxwell-Boltzmann density at the mean temperature, for the mix of masses in the latest frame
+    pub fn expected_density(&self, speed: f64) -> f64 {
+        let temperature = self.temperature();
+        if self.masses.is_empty() || temperature <= 0.0 {
+            return 0.0;
+        }
+        self.masses.iter().map(|&m| maxwell_boltzmann(speed, m as f64, temperature)).sum::<f64>() / self.masses.len() as f64
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::vector_math::Vector2f;
+
+    #[test]
+    fn maxwell_boltzmann_is_normalised() {
+        for &(mass, temperature) in &[(1.0f64, 1.0f64), (2.0, 500.0), (0.5, 20000.0)] {
+            // Midpoint rule out to well beyond the most probable speed, sqrt(T / m)
+            let width = 20.0 * (temperature / mass).sqrt() / 10000.0;
+            let total: f64 = (0..10000).map(|i| maxwell_boltzmann((i as f64 + 0.5) * width, mass, temperature) * width).sum();
+            assert!((total - 1.0).abs() < 1e-6, "integrates to {} for mass {} at {}", total, mass, temperature);
+        }
+    }
+
+
+    #[test]
+    fn densities_integrate_to_one() {
+        let mut histogram = SpeedHistogram::new(20, 3);
+        for frame in 0..5 {
+            let balls: Vec<Ball> = (1..=10)
+                .map(|i| Ball::new(Vector2f::default(), Vector2f{x: (i + frame) as f32, y: 0.0}, 1.0, 1.0))
+                .collect();
+            histogram.record(&balls);
+        }
+        // Only the last three frames count, with speeds from 3 to 12, 4 to 13 and 5 to 14, which are
+        // all below the top of the last bin
+        assert!((histogram.temperature() - (32.25 + 40.25 + 49.25) / 3.0).abs() < 1e-9);
+        assert!(histogram.max_speed() > 14.0);
+        let total: f64 = histogram.densities().iter().sum::<f64>() * histogram.bin_width();
+        assert!((total - 1.0).abs() < 1e-12, "integrates to {}", total);
+    }
+}
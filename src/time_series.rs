@@ -0,0 +1,112 @@
+// Logs observables to CSV at a fixed interval of simulated time, for analysis outside the viewer.
+// Attach a log with World::set_log; World::update then takes a sample at the end of each update
+// during which a multiple of the interval passes, at most one per update, so that logging doesn't
+// change the run. The time column gives the time each sample was actually taken. The file starts with comment lines recording the seed and the scene, so that the
+// run can be reproduced from the log alone, then a row of column names. For example
+//
+//   # seed: 42
+//   # scene:
+//   # (
+//   #     seed: Some(42),
+//   #     ...
+//   # )
+//   time,energy,momentum x,momentum y
+//   0,1500.25,0.125,-3.5
+//   0.1,1500.25,0.125,-3.5
+use std::io::{self, Write};
+use super::world::World;
+use super::scene::Scene;
+use super::observables::{self, Observable};
+
+
+pub struct TimeSeriesLog {
+    out: Box<dyn Write>,
+    observables: Vec<Box<dyn Observable>>,
+    interval: f64,
+    // The first error writing the log. Nothing more is written after one.
+    error: Option<io::Error>,
+}
+
+
+impl TimeSeriesLog {
+    // Writes the header and a first sample of world as it is now. scene is recorded in the header;
+    // for a world that was not built from a scene, pass Scene::from_world.
+    pub fn new(out: Box<dyn Write>, observables: Vec<Box<dyn Observable>>, interval: f64,
+               world: &World, scene: &Scene) -> io::Result<TimeSeriesLog> {
+        assert!(interval > 0.0 && interval.is_finite(), "the interval between samples must be positive");
+        let mut log = TimeSeriesLog{out, observables, interval, error: None};
+        log.write_header(world, scene)?;
+        log.sample(world);
+        match log.error.take() {
+            Some(e) => Err(e),
+            None => Ok(log),
+        }
+    }
+
+
+    // Energy, momentum, pressure on each wall, collision counts by kind and temperature
+    pub fn default_observables() -> Vec<Box<dyn Observable>> {
+        ["energy", "momentum", "wall pressure", "collisions", "temperature"].iter()
+            .filter_map(|name| observables::named(name))
+            .collect()
+    }
+
+
+    pub fn get_interval(&self) -> f64 {
+        self.interval
+    }
+
+
+    // The time of the next sample after time, going forwards or backwards depending on direction
+    pub fn next_sample(&self, time: f64, direction: f32) -> f64 {
+        // The tolerance stops rounding error putting the next sample a vanishingly short time away
+        let index = time / self.interval;
+        if direction >= 0.0 {
+            ((index + 1e-9).floor() + 1.0) * self.interval
+        } else {
+            ((index - 1e-9).ceil() - 1.0) * self.interval
+        }
+    }
+
+
+    // Writes a row for the world as it is now
+    pub fn sample(&mut self, world: &World) {
+        if self.error.is_some() {
+            return;
+        }
+        let mut row = vec![world.get_time().to_string()];
+        for observable in &mut self.observables {
+            observable.observe(world);
+            row.extend(observable.values(world).iter().map(f64::to_string));
+        }
+        if let Err(e) = writeln!(self.out, "{}", row.join(",")) {
+            self.error = Some(e);
+        }
+    }
+
+
+    // Flushes the log, returning the first error writing it if there was one
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()
+    }
+
+
+    fn write_header(&mut self, world: &World, scene: &Scene) -> io::Result<()> {
+        match world.get_seed() {
+            Some(seed) => writeln!(self.out, "# seed: {}", seed)?,
+            None       => writeln!(self.out, "# seed: unknown")?,
+        }
+        writeln!(self.out, "# scene:")?;
+        for line in scene.to_ron().lines() {
+            writeln!(self.out, "# {}", line)?;
+        }
+        let mut columns = vec![String::from("time")];
+        for observable in &self.observables {
+            columns.extend(observable.labels(world));
+        }
+        writeln!(self.out, "{}", columns.join(","))
+    }
+}
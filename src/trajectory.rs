@@ -0,0 +1,505 @@
+// Trajectory files: a record of a run that can be played back without simulating it again.
+//
+// Between collisions every ball flies freely under the field, so the run is recorded as the state of
+// each ball just after each collision that changes it, with snapshots of every ball at the start and
+// then every keyframe interval so that playback can seek without replaying from the beginning. The
+// position of any ball at any time is then exactly where its free flight from its last recorded
+// state takes it. Worlds of soft balls have no such events, so for them, and in
+// TrajectoryMode::Frames, a snapshot is taken after every update instead.
+//
+// The file starts with a header holding the scene the run started from, as RON, followed by the
+// records, all encoded with bincode. Recording needs time to run forwards and the same balls
+// throughout; adding or removing walls while recording is not supported either.
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use serde::{Serialize, Deserialize};
+use super::ball::Ball;
+use super::wall::Wall;
+use super::world::World;
+use super::scene::{Scene, SceneError};
+use super::vector_math::Vector2f;
+
+
+const FORMAT: &str = "particle_sim trajectory";
+const VERSION: u32 = 1;
+
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub enum TrajectoryMode {
+    // A snapshot every keyframe interval, and the state of each ball after every collision
+    #[default]
+    Events,
+    // A snapshot after every update
+    Frames,
+}
+
+
+// What changes over time about a ball
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BallState {
+    pub position: Vector2f,
+    pub velocity: Vector2f,
+    pub orientation: f32,
+    pub angular_velocity: f32,
+}
+
+
+pub struct TrajectoryRecorder {
+    out: Box<dyn Write>,
+    mode: TrajectoryMode,
+    keyframe_interval: f64,
+    last_snapshot: f64,
+    time: f64,
+    ball_count: usize,
+    // The walls when recording began, which wall offsets are measured from
+    walls: Vec<Wall>,
+    // The first error writing the trajectory, or a change to the world that cannot be recorded.
+    // Nothing more is written after one.
+    error: Option<TrajectoryError>,
+}
+
+
+// A trajectory file, loaded for playback
+pub struct Trajectory {
+    scene: Scene,
+    keyframes: Vec<Keyframe>,
+    changes: Vec<Change>,
+    end_time: f64,
+}
+
+
+// Shows a trajectory as it was at any time, in a World that is never updated
+pub struct Playback {
+    trajectory: Trajectory,
+    world: World,
+    walls: Vec<Wall>,
+    time: f64,
+}
+
+
+#[derive(Debug)]
+pub enum TrajectoryError {
+    Io(io::Error),
+    // Not a trajectory file, or a damaged one
+    Format(String),
+    Scene(SceneError),
+    // A change to the world that the trajectory cannot represent
+    Unrecordable(String),
+}
+
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: String,
+    version: u32,
+    scene: String,
+}
+
+
+#[derive(Serialize, Deserialize)]
+enum Record {
+    // Every ball, and how far each wall has moved since recording began
+    Snapshot { time: f64, balls: Vec<BallState>, wall_offsets: Vec<Vector2f> },
+    Change(Change),
+    // The time recording stopped
+    End(f64),
+}
+
+
+// The state of one ball just after a collision
+#[derive(Copy, Clone, Serialize, Deserialize)]
+struct Change {
+    time: f64,
+    ball: u32,
+    state: BallState,
+}
+
+
+struct Keyframe {
+    time: f64,
+    balls: Vec<BallState>,
+    wall_offsets: Vec<Vector2f>,
+    // The index of the first change recorded after the snapshot
+    first_change: usize,
+}
+
+
+impl BallState {
+    pub fn of(ball: &Ball) -> BallState {
+        BallState{position: ball.get_position(), velocity: ball.velocity, orientation: ball.orientation,
+                  angular_velocity: ball.angular_velocity}
+    }
+
+
+    pub fn apply(&self, ball: &mut Ball) {
+        ball.circle.position = self.position;
+        ball.velocity = self.velocity;
+        ball.orientation = self.orientation;
+        ball.angular_velocity = self.angular_velocity;
+    }
+}
+
+
+impl TrajectoryRecorder {
+    // Writes the header and a first snapshot of world as it is now
+    pub fn new(out: Box<dyn Write>, mode: TrajectoryMode, keyframe_interval: f64, world: &World)
+               -> Result<TrajectoryRecorder, TrajectoryError> {
+        assert!(keyframe_interval > 0.0, "the keyframe interval must be positive");
+        let mut recorder = TrajectoryRecorder{out, mode, keyframe_interval, last_snapshot: world.get_time(),
+                                              time: world.get_time(), ball_count: world.get_balls().len(),
+                                              walls: world.get_walls().to_vec(), error: None};
+        let header = Header{format: String::from(FORMAT), version: VERSION, scene: Scene::from_world(world).to_ron()};
+        bincode::serialize_into(&mut recorder.out, &header).map_err(TrajectoryError::from)?;
+        recorder.snapshot(world);
+        match recorder.error.take() {
+            Some(e) => Err(e),
+            None => Ok(recorder),
+        }
+    }
+
+
+    pub fn create(path: &str, mode: TrajectoryMode, keyframe_interval: f64, world: &World)
+                  -> Result<TrajectoryRecorder, TrajectoryError> {
+        let out = BufWriter::new(File::create(path).map_err(TrajectoryError::Io)?);
+        TrajectoryRecorder::new(Box::new(out), mode, keyframe_interval, world)
+    }
+
+
+    pub fn get_mode(&self) -> TrajectoryMode {
+        self.mode
+    }
+
+
+    // Called by World::update before it steps the world on by dt
+    pub(crate) fn begin_update(&mut self, balls: &[Ball], dt: f32) {
+        if self.error.is_some() {
+            return;
+        }
+        if dt < 0.0 {
+            self.error = Some(TrajectoryError::Unrecordable(String::from("time ran backwards")));
+        } else if balls.len() != self.ball_count {
+            self.error = Some(TrajectoryError::Unrecordable(String::from("the number of balls changed")));
+        }
+    }
+
+
+    // Called by World::update after resolving a collision that changed ball index
+    pub(crate) fn record_change(&mut self, time: f64, index: usize, ball: &Ball) {
+        self.write(&Record::Change(Change{time, ball: index as u32, state: BallState::of(ball)}));
+    }
+
+
+    // Called by World::update when it has finished a step
+    pub(crate) fn end_update(&mut self, world: &World) {
+        self.time = world.get_time();
+        let frames = self.mode == TrajectoryMode::Frames || world.get_integrator().potential().is_some();
+        if frames || self.time - self.last_snapshot >= self.keyframe_interval {
+            self.snapshot(world);
+        }
+    }
+
+
+    // Marks the end of the recording and flushes it, returning the first error recording it if there
+    // was one
+    pub fn finish(mut self) -> Result<(), TrajectoryError> {
+        let time = self.time;
+        self.write(&Record::End(time));
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush().map_err(TrajectoryError::Io)
+    }
+
+
+    fn snapshot(&mut self, world: &World) {
+        let wall_offsets = self.walls.iter().zip(world.get_walls())
+            .map(|(start, now)| wall_position(now) - wall_position(start))
+            .collect();
+        let balls = world.get_balls().iter().map(BallState::of).collect();
+        self.last_snapshot = world.get_time();
+        self.write(&Record::Snapshot{time: world.get_time(), balls, wall_offsets});
+    }
+
+
+    fn write(&mut self, record: &Record) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(e) = bincode::serialize_into(&mut self.out, record) {
+            self.error = Some(TrajectoryError::from(e));
+        }
+    }
+}
+
+
+// A point that moves with the wall
+fn wall_position(wall: &Wall) -> Vector2f {
+    match wall {
+        Wall::Plane(plane) => plane.position,
+        Wall::Segment(segment) => segment.start,
+        Wall::Arc(arc) => arc.centre,
+    }
+}
+
+
+impl Trajectory {
+    pub fn load(path: &str) -> Result<Trajectory, TrajectoryError> {
+        Trajectory::read(BufReader::new(File::open(path).map_err(TrajectoryError::Io)?))
+    }
+
+
+    // A recording cut short, for example by a crash, ends at its last complete record
+    pub fn read<R: Read>(mut input: R) -> Result<Trajectory, TrajectoryError> {
+        let header: Header = bincode::deserialize_from(&mut input).map_err(TrajectoryError::from)?;
+        if header.format != FORMAT {
+            return Err(TrajectoryError::Format(String::from("not a trajectory file")));
+        }
+        if header.version != VERSION {
+            return Err(TrajectoryError::Format(format!("unsupported version {}", header.version)));
+        }
+        let scene = Scene::parse(&header.scene).map_err(TrajectoryError::Scene)?;
+
+        let mut keyframes: Vec<Keyframe> = Vec::new();
+        let mut changes: Vec<Change> = Vec::new();
+        let mut end_time = None;
+        loop {
+            let record = match bincode::deserialize_from(&mut input) {
+                Ok(record) => record,
+                Err(e) => match *e {
+                    bincode::ErrorKind::Io(ref io) if io.kind() == io::ErrorKind::UnexpectedEof => break,
+                    _ => return Err(TrajectoryError::from(e)),
+                },
+            };
+            match record {
+                Record::Snapshot{time, balls, wall_offsets} => {
+                    keyframes.push(Keyframe{time, balls, wall_offsets, first_change: changes.len()});
+                },
+                Record::Change(change) => changes.push(change),
+                Record::End(time) => {
+                    end_time = Some(time);
+                    break;
+                },
+            }
+        }
+
+        let first = keyframes.first().ok_or_else(|| TrajectoryError::Format(String::from("no snapshots")))?;
+        if changes.iter().any(|c| c.ball as usize >= first.balls.len()) {
+            return Err(TrajectoryError::Format(String::from("a change to a ball that does not exist")));
+        }
+        let last_time = keyframes.iter().map(|k| k.time).chain(changes.iter().map(|c| c.time)).fold(first.time, f64::max);
+        Ok(Trajectory{scene, keyframes, changes, end_time: end_time.unwrap_or(last_time)})
+    }
+
+
+    // The scene the run started from
+    pub fn get_scene(&self) -> &Scene {
+        &self.scene
+    }
+
+
+    pub fn start_time(&self) -> f64 {
+        self.keyframes[0].time
+    }
+
+
+    pub fn end_time(&self) -> f64 {
+        self.end_time
+    }
+
+
+    // The last keyframe at or before time, or the first if time is before the start
+    fn keyframe(&self, time: f64) -> &Keyframe {
+        let index = self.keyframes.partition_point(|k| k.time <= time);
+        &self.keyframes[index.saturating_sub(1)]
+    }
+}
+
+
+impl Playback {
+    pub fn new(trajectory: Trajectory) -> Result<Playback, TrajectoryError> {
+        let world = trajectory.scene.build().map_err(TrajectoryError::Scene)?;
+        if trajectory.keyframes.iter().any(|k| k.balls.len() != world.get_balls().len()) {
+            return Err(TrajectoryError::Format(String::from("the snapshots do not match the scene")));
+        }
+        let walls = world.get_walls().to_vec();
+        let mut playback = Playback{time: trajectory.start_time(), trajectory, world, walls};
+        playback.seek(playback.time);
+        Ok(playback)
+    }
+
+
+    pub fn get_trajectory(&self) -> &Trajectory {
+        &self.trajectory
+    }
+
+
+    pub fn get_world(&self) -> &World {
+        &self.world
+    }
+
+
+    pub fn get_time(&self) -> f64 {
+        self.time
+    }
+
+
+    // Puts every ball and wall where it was at time, clamped to the span of the recording
+    pub fn seek(&mut self, time: f64) {
+        let time = time.max(self.trajectory.start_time()).min(self.trajectory.end_time());
+        let keyframe = self.trajectory.keyframe(time);
+        let mut states: Vec<(f64, BallState)> = keyframe.balls.iter().map(|s| (keyframe.time, *s)).collect();
+        for change in self.trajectory.changes[keyframe.first_change..].iter().take_while(|c| c.time <= time) {
+            states[change.ball as usize] = (change.time, change.state);
+        }
+
+        let field = self.world.get_field();
+        let periodic = self.world.get_periodic();
+        for (i, (since, state)) in states.iter().enumerate() {
+            let ball = self.world.get_ball_mut(i).expect("the snapshots match the scene");
+            state.apply(ball);
+            let t = (time - since) as f32;
+            if t != 0.0 {
+                let acceleration = field.acceleration(ball);
+                ball.advance(t, acceleration);
+            }
+            ball.circle.position = periodic.wrap(ball.get_position());
+        }
+
+        let dt = (time - keyframe.time) as f32;
+        for (i, (wall, offset)) in self.walls.iter().zip(&keyframe.wall_offsets).enumerate() {
+            let mut wall = *wall;
+            let moved = if dt != 0.0 { wall.motion().velocity(keyframe.time, dt) * dt } else { Vector2f::default() };
+            wall.translate(*offset + moved);
+            self.world.set_wall(i, wall);
+        }
+        self.world.set_time(time);
+        self.time = time;
+    }
+}
+
+
+impl From<bincode::Error> for TrajectoryError {
+    fn from(e: bincode::Error) -> TrajectoryError {
+        match *e {
+            bincode::ErrorKind::Io(e) => TrajectoryError::Io(e),
+            e => TrajectoryError::Format(e.to_string()),
+        }
+    }
+}
+
+
+impl fmt::Display for TrajectoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrajectoryError::Io(e)           => write!(f, "{}", e),
+            TrajectoryError::Format(reason)  => write!(f, "invalid trajectory: {}", reason),
+            TrajectoryError::Scene(e)        => write!(f, "scene: {}", e),
+            TrajectoryError::Unrecordable(r) => write!(f, "cannot record: {}", r),
+        }
+    }
+}
+
+
+impl std::error::Error for TrajectoryError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    // A buffer that can still be read once a recorder owns it
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+
+    fn scene() -> Scene {
+        Scene::parse("(seed: Some(21), container: Box((left: 0.0, top: 0.0, width: 400.0, height: 400.0)),
+            field: Gravity((x: 0.0, y: 200.0)),
+            spawn: [(count: 40, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 8.0, mass: 1.0,
+                     speed: Uniform(min: 50.0, max: 300.0))])").unwrap()
+    }
+
+
+    // Records 90 updates of the scene, returning the file and the positions of the balls after each
+    // update
+    fn record() -> (Vec<u8>, Vec<Vec<Vector2f>>) {
+        let buffer = Shared::default();
+        let mut world = scene().build().unwrap();
+        let recorder = TrajectoryRecorder::new(Box::new(buffer.clone()), TrajectoryMode::Events, 0.25, &world).unwrap();
+        world.set_recorder(recorder);
+        let mut positions = vec![world.get_balls().iter().map(Ball::get_position).collect()];
+        for _ in 0..90 {
+            world.update(1.0 / 60.0);
+            positions.push(world.get_balls().iter().map(Ball::get_position).collect());
+        }
+        world.take_recorder().unwrap().finish().unwrap();
+        let bytes = buffer.0.borrow().clone();
+        (bytes, positions)
+    }
+
+
+    fn assert_near(world: &World, positions: &[Vector2f]) {
+        for (ball, expected) in world.get_balls().iter().zip(positions) {
+            let d = ball.get_position() - *expected;
+            assert!(d.x.hypot(d.y) < 1e-2, "at {:?} instead of {:?}", ball.get_position(), expected);
+        }
+    }
+
+
+    #[test]
+    fn playback_reproduces_the_run() {
+        let (bytes, positions) = record();
+        let mut playback = Playback::new(Trajectory::read(&bytes[..]).unwrap()).unwrap();
+        assert!((playback.get_trajectory().end_time() - 1.5).abs() < 1e-6);
+        // On keyframes, between them, out of order and at the end
+        for &update in &[0, 15, 37, 52, 20, 89, 90] {
+            playback.seek(update as f64 / 60.0);
+            assert_near(playback.get_world(), &positions[update]);
+        }
+
+        // Part way through an update, against a world stepped there directly
+        let mut world = scene().build().unwrap();
+        world.update(0.3);
+        playback.seek(world.get_time());
+        assert_near(playback.get_world(), &world.get_balls().iter().map(Ball::get_position).collect::<Vec<_>>());
+    }
+
+
+    #[test]
+    fn truncated_trajectory_ends_at_its_last_record() {
+        let (bytes, positions) = record();
+        let trajectory = Trajectory::read(&bytes[..bytes.len() * 2 / 3]).unwrap();
+        let end = trajectory.end_time();
+        assert!(end > 0.5 && end < 1.5, "ends at {}", end);
+        let mut playback = Playback::new(trajectory).unwrap();
+        playback.seek(0.5);
+        assert_near(playback.get_world(), &positions[30]);
+
+        // Cut inside the header, it isn't a trajectory at all
+        assert!(Trajectory::read(&bytes[..10]).is_err());
+    }
+
+
+    #[test]
+    fn running_backwards_is_unrecordable() {
+        let mut world = scene().build().unwrap();
+        let recorder = TrajectoryRecorder::new(Box::new(Shared::default()), TrajectoryMode::Events, 0.25, &world).unwrap();
+        world.set_recorder(recorder);
+        world.update(1.0 / 60.0);
+        world.update(-1.0 / 60.0);
+        assert!(matches!(world.take_recorder().unwrap().finish(), Err(TrajectoryError::Unrecordable(_))));
+    }
+}
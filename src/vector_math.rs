@@ -1,5 +1,112 @@
 #![allow(dead_code)]
-use sfml::system::Vector2f;
+use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg};
+use serde::{Serialize, Deserialize};
+
+
+// A 2D vector. The physics uses this rather than SFML's vector so that it can be built without SFML;
+// conversions are provided for drawing when the gui feature is enabled.
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Vector2<T> {
+    pub x: T,
+    pub y: T,
+}
+
+pub type Vector2f = Vector2<f32>;
+
+
+impl<T> Vector2<T> {
+    pub fn new(x: T, y: T) -> Vector2<T> {
+        Vector2{x, y}
+    }
+}
+
+
+impl<T: Add<Output = T>> Add for Vector2<T> {
+    type Output = Vector2<T>;
+    fn add(self, rhs: Vector2<T>) -> Vector2<T> {
+        Vector2{x: self.x + rhs.x, y: self.y + rhs.y}
+    }
+}
+
+
+impl<T: AddAssign> AddAssign for Vector2<T> {
+    fn add_assign(&mut self, rhs: Vector2<T>) {
+        self.x += rhs.x;
+        self.y += rhs.y;
+    }
+}
+
+
+impl<T: Sub<Output = T>> Sub for Vector2<T> {
+    type Output = Vector2<T>;
+    fn sub(self, rhs: Vector2<T>) -> Vector2<T> {
+        Vector2{x: self.x - rhs.x, y: self.y - rhs.y}
+    }
+}
+
+
+impl<T: SubAssign> SubAssign for Vector2<T> {
+    fn sub_assign(&mut self, rhs: Vector2<T>) {
+        self.x -= rhs.x;
+        self.y -= rhs.y;
+    }
+}
+
+
+impl<T: Mul<Output = T> + Copy> Mul<T> for Vector2<T> {
+    type Output = Vector2<T>;
+    fn mul(self, rhs: T) -> Vector2<T> {
+        Vector2{x: self.x * rhs, y: self.y * rhs}
+    }
+}
+
+
+impl<T: MulAssign + Copy> MulAssign<T> for Vector2<T> {
+    fn mul_assign(&mut self, rhs: T) {
+        self.x *= rhs;
+        self.y *= rhs;
+    }
+}
+
+
+impl<T: Div<Output = T> + Copy> Div<T> for Vector2<T> {
+    type Output = Vector2<T>;
+    fn div(self, rhs: T) -> Vector2<T> {
+        Vector2{x: self.x / rhs, y: self.y / rhs}
+    }
+}
+
+
+impl<T: DivAssign + Copy> DivAssign<T> for Vector2<T> {
+    fn div_assign(&mut self, rhs: T) {
+        self.x /= rhs;
+        self.y /= rhs;
+    }
+}
+
+
+impl<T: Neg<Output = T>> Neg for Vector2<T> {
+    type Output = Vector2<T>;
+    fn neg(self) -> Vector2<T> {
+        Vector2{x: -self.x, y: -self.y}
+    }
+}
+
+
+#[cfg(feature = "gui")]
+impl From<Vector2f> for sfml::system::Vector2f {
+    fn from(v: Vector2f) -> sfml::system::Vector2f {
+        sfml::system::Vector2f{x: v.x, y: v.y}
+    }
+}
+
+
+#[cfg(feature = "gui")]
+impl From<sfml::system::Vector2f> for Vector2f {
+    fn from(v: sfml::system::Vector2f) -> Vector2f {
+        Vector2f{x: v.x, y: v.y}
+    }
+}
 
 
 pub fn length_squared(vec: &Vector2f) -> f32 {
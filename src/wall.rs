@@ -0,0 +1,519 @@
+// The fixed walls balls bounce off: infinite planes, and finite segments and arcs of circles
+use serde::{Serialize, Deserialize};
+use super::ball::Ball;
+use super::plane::{Plane, Motion};
+use super::vector_math::{self, Vector2, Vector2f, dot_product};
+use super::math::convert_vector2;
+use super::restitution::default_restitution;
+use super::geometry::Rect;
+
+
+// Untagged, so that scene files can list planes and segments side by side by their fields alone
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Wall {
+    Plane(Plane),
+    Segment(Segment),
+    Arc(CircularArc),
+}
+
+
+// A finite wall from start to end. Balls bounce off either side of it and off its ends.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Segment {
+    pub start: Vector2f,
+    pub end: Vector2f,
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    #[serde(default)]
+    pub friction: f32,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+}
+
+
+// Part of a circle, from start_angle turning through sweep in the sense of positive angles (from the
+// x axis towards the y axis). Like segments, arcs can be hit from either side and on their ends.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CircularArc {
+    pub centre: Vector2f,
+    pub radius: f32,
+    #[serde(default)]
+    pub start_angle: f32,
+    // A whole circle by default
+    #[serde(default = "full_turn")]
+    pub sweep: f32,
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    #[serde(default)]
+    pub friction: f32,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+}
+
+
+impl Segment {
+    pub fn new(start: Vector2f, end: Vector2f) -> Segment {
+        Segment{start, end, restitution: 1.0, friction: 0.0, temperature: None}
+    }
+
+
+    // The closed polygon through the given vertices
+    pub fn polygon(vertices: &[Vector2f]) -> Vec<Segment> {
+        (0..vertices.len())
+            .map(|i| Segment::new(vertices[i], vertices[(i + 1) % vertices.len()]))
+            .collect()
+    }
+
+
+    pub fn length(&self) -> f32 {
+        vector_math::length_squared(&(self.end - self.start)).sqrt()
+    }
+
+
+    // The point on the segment nearest to point
+    pub fn closest_point(&self, point: Vector2f) -> Vector2f {
+        let direction = self.end - self.start;
+        let s = dot_product(&(point - self.start), &direction) / vector_math::length_squared(&direction);
+        self.start + direction * s.clamp(0.0, 1.0)
+    }
+
+
+    // The time until the ball hits the segment, either on one of its faces or on one of its ends
+    pub fn collision_time(&self, ball: &Ball, acceleration: Vector2f, invert_time: bool) -> Option<f32> {
+        let direction = self.end - self.start;
+        let length_squared = vector_math::length_squared(&direction);
+        let normal = Vector2f{x: -direction.y, y: direction.x};
+        let on_segment = |t: f32| {
+            let s = dot_product(&(ball_after(ball, t, acceleration).get_position() - self.start), &direction) / length_squared;
+            (0.0..=1.0).contains(&s)
+        };
+
+        // Either face, as a plane, and whether the point of contact is within the length of the segment
+        let entries = [normal, -normal].iter()
+            .flat_map(|&n| ball.plane_contact_times(&Plane::new(self.start, n), acceleration, invert_time))
+            .map(|t| (t, on_segment(t)))
+            .collect();
+        let distance = dot_product(&normal, &(ball.get_position() - self.start)).abs() / length_squared.sqrt();
+        first_hit(entries, distance < ball.circle.radius, &[self.start, self.end], ball, acceleration, invert_time)
+    }
+}
+
+
+impl CircularArc {
+    // A whole circle
+    pub fn circle(centre: Vector2f, radius: f32) -> CircularArc {
+        CircularArc{centre, radius, start_angle: 0.0, sweep: full_turn(), restitution: 1.0, friction: 0.0, temperature: None}
+    }
+
+
+    pub fn is_circle(&self) -> bool {
+        self.sweep >= full_turn()
+    }
+
+
+    // Whether the ray from the centre at the given angle passes through the arc
+    pub fn spans(&self, angle: f32) -> bool {
+        self.is_circle() || (angle - self.start_angle).rem_euclid(full_turn()) <= self.sweep
+    }
+
+
+    pub fn ends(&self) -> Vec<Vector2f> {
+        if self.is_circle() {
+            return Vec::new();
+        }
+        [self.start_angle, self.start_angle + self.sweep].iter()
+            .map(|&angle| self.centre + vector_math::rotate(&Vector2f{x: self.radius, y: 0.0}, angle))
+            .collect()
+    }
+
+
+    // The point on the arc nearest to point
+    pub fn closest_point(&self, point: Vector2f) -> Vector2f {
+        let angle = vector_math::angle_rad(&(point - self.centre));
+        if self.spans(angle) {
+            return self.centre + vector_math::rotate(&Vector2f{x: self.radius, y: 0.0}, angle);
+        }
+        let ends = self.ends();
+        let distance = |end: &Vector2f| vector_math::length_squared(&(point - *end));
+        if distance(&ends[0]) <= distance(&ends[1]) {ends[0]} else {ends[1]}
+    }
+
+
+    // The time until the ball hits the arc, from the inside or the outside, or one of its ends
+    pub fn collision_time(&self, ball: &Ball, acceleration: Vector2f, invert_time: bool) -> Option<f32> {
+        let on_arc = |t: f32| self.spans(vector_math::angle_rad(&(ball_after(ball, t, acceleration).get_position() - self.centre)));
+        let entries = [true, false].iter()
+            .flat_map(|&inside| ball.circle_contact_times(self.centre, self.radius, acceleration, inside, invert_time))
+            .map(|t| (t, on_arc(t)))
+            .collect();
+        let distance = vector_math::length_squared(&(ball.get_position() - self.centre)).sqrt();
+        let in_band = (distance - self.radius).abs() < ball.circle.radius;
+        first_hit(entries, in_band, &self.ends(), ball, acceleration, invert_time)
+    }
+}
+
+
+// The first collision with a wall that has the given ends. entries holds every time the ball comes
+// to touch the wall or its extension (the rest of the line or circle it lies on), with whether the
+// point of contact is on the wall itself. in_band is whether the ball is overlapping the extension now.
+fn first_hit(mut entries: Vec<(f32, bool)>, in_band: bool, ends: &[Vector2f], ball: &Ball, acceleration: Vector2f,
+             invert_time: bool) -> Option<f32> {
+    entries.sort_by(|a, b| a.0.abs().total_cmp(&b.0.abs()));
+    // Until the ball first overlaps the extension, it can't hit an end
+    if !in_band {
+        match entries.first() {
+            None => return None,
+            Some(&(t, true)) => return Some(t),
+            Some(_) => (),
+        }
+    }
+
+    // The ends, as stationary balls of no size
+    let on_ends = ends.iter().filter_map(|&end| {
+        let point = Ball::new(end, Vector2f::default(), 0.0, 1.0);
+        Ball::collision_time(ball, &point, acceleration, Vector2f::default(), invert_time)
+    });
+    entries.iter().filter(|e| e.1).map(|e| e.0)
+        .chain(on_ends)
+        .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+}
+
+
+fn ball_after(ball: &Ball, t: f32, acceleration: Vector2f) -> Ball {
+    let mut ball = *ball;
+    ball.advance(t, acceleration);
+    ball
+}
+
+
+fn full_turn() -> f32 {
+    2.0 * std::f32::consts::PI
+}
+
+
+impl Wall {
+    pub fn restitution(&self) -> f32 {
+        match self {
+            Wall::Plane(plane) => plane.restitution,
+            Wall::Segment(segment) => segment.restitution,
+            Wall::Arc(arc) => arc.restitution,
+        }
+    }
+
+
+    pub fn friction(&self) -> f32 {
+        match self {
+            Wall::Plane(plane) => plane.friction,
+            Wall::Segment(segment) => segment.friction,
+            Wall::Arc(arc) => arc.friction,
+        }
+    }
+
+
+    // The temperature of a thermal wall, None for an ordinary one
+    pub fn temperature(&self) -> Option<f32> {
+        match self {
+            Wall::Plane(plane) => plane.temperature,
+            Wall::Segment(segment) => segment.temperature,
+            Wall::Arc(arc) => arc.temperature,
+        }
+    }
+
+
+    // Only planes can move
+    pub fn motion(&self) -> Motion {
+        match self {
+            Wall::Plane(plane) => plane.motion,
+            Wall::Segment(_) | Wall::Arc(_) => Motion::default(),
+        }
+    }
+
+
+    // Moves the wall by displacement
+    pub fn translate(&mut self, displacement: Vector2f) {
+        match self {
+            Wall::Plane(plane) => plane.position += displacement,
+            Wall::Segment(segment) => {
+                segment.start += displacement;
+                segment.end += displacement;
+            },
+            Wall::Arc(arc) => arc.centre += displacement,
+        }
+    }
+
+
+    // The distance from point to the wall, negative behind a plane
+    pub fn distance(&self, point: Vector2f) -> f32 {
+        match self {
+            Wall::Plane(plane) => dot_product(&plane.normal, &(point - plane.position)),
+            Wall::Segment(segment) => vector_math::length_squared(&(point - segment.closest_point(point))).sqrt(),
+            Wall::Arc(arc) => vector_math::length_squared(&(point - arc.closest_point(point))).sqrt(),
+        }
+    }
+
+
+    // The plane through the point on the wall nearest to point, facing it. A ball touching the wall
+    // collides with it as it would with this plane.
+    pub fn contact_plane(&self, point: Vector2f) -> Plane {
+        match self {
+            Wall::Plane(plane) => *plane,
+            Wall::Segment(segment) => {
+                let closest = segment.closest_point(point);
+                // Beside the segment, rather than past an end, the normal is perpendicular to it on the
+                // side of point, which stays accurate however near point is. On it either side will do.
+                let direction = segment.end - segment.start;
+                let perpendicular = Vector2f{x: -direction.y, y: direction.x};
+                let beside = closest != segment.start && closest != segment.end;
+                let normal = if beside || point == closest {
+                    if dot_product(&(point - segment.start), &perpendicular) < 0.0 { -perpendicular } else { perpendicular }
+                } else {
+                    point - closest
+                };
+                Plane{restitution: segment.restitution, friction: segment.friction, temperature: segment.temperature,
+                      ..Plane::new(closest, normal)}
+            },
+            Wall::Arc(arc) => {
+                let closest = arc.closest_point(point);
+                // Within the arc's sweep the normal is along the radius, towards point, which stays
+                // accurate however near point is. On the arc either side will do.
+                let radial = point - arc.centre;
+                let normal = if radial != Vector2f::default() && arc.spans(vector_math::angle_rad(&radial)) {
+                    if vector_math::length_squared(&radial) < arc.radius * arc.radius { -radial } else { radial }
+                } else {
+                    point - closest
+                };
+                Plane{restitution: arc.restitution, friction: arc.friction, temperature: arc.temperature,
+                      ..Plane::new(closest, normal)}
+            },
+        }
+    }
+
+
+    pub fn collision_time(&self, ball: &Ball, acceleration: Vector2f, invert_time: bool) -> Option<f32> {
+        match self {
+            Wall::Plane(plane) => ball.plane_collision_time(plane, acceleration, invert_time),
+            Wall::Segment(segment) => segment.collision_time(ball, acceleration, invert_time),
+            Wall::Arc(arc) => arc.collision_time(ball, acceleration, invert_time),
+        }
+    }
+
+
+    // The length of a segment or arc, or of the part of a plane that crosses rect
+    pub fn length_within(&self, rect: &Rect) -> f32 {
+        match self {
+            Wall::Plane(plane) => {
+                // Clip the line through the plane to the rectangle, one axis at a time
+                let along = Vector2f{x: -plane.normal.y, y: plane.normal.x};
+                let mut range = (f32::NEG_INFINITY, f32::INFINITY);
+                let axes = [(plane.position.x, along.x, rect.left, rect.left + rect.width),
+                            (plane.position.y, along.y, rect.top, rect.top + rect.height)];
+                for &(p, d, min, max) in &axes {
+                    if d == 0.0 {
+                        if p < min || p > max {
+                            return 0.0;
+                        }
+                    } else {
+                        let (t0, t1) = ((min - p) / d, (max - p) / d);
+                        range = (range.0.max(t0.min(t1)), range.1.min(t0.max(t1)));
+                    }
+                }
+                (range.1 - range.0).max(0.0)
+            },
+            Wall::Segment(segment) => segment.length(),
+            Wall::Arc(arc) => arc.radius * arc.sweep.min(full_turn()),
+        }
+    }
+
+
+    // The corners of a box containing the wall, or None for a plane, which is infinite
+    pub fn extent(&self) -> Option<(Vector2f, Vector2f)> {
+        match self {
+            Wall::Plane(_) => None,
+            Wall::Segment(segment) => Some((Vector2f{x: segment.start.x.min(segment.end.x), y: segment.start.y.min(segment.end.y)},
+                                            Vector2f{x: segment.start.x.max(segment.end.x), y: segment.start.y.max(segment.end.y)})),
+            // The whole circle's, even for an arc
+            Wall::Arc(arc) => {
+                let r = Vector2f{x: arc.radius, y: arc.radius};
+                Some((arc.centre - r, arc.centre + r))
+            },
+        }
+    }
+}
+
+
+// The areas enclosed by the closed loops that segments and arcs among the walls form, end to end and
+// in any order. Whole circles are loops by themselves. Walls that aren't part of a loop, such as
+// baffles, enclose nothing.
+pub fn loop_areas(walls: &[Wall]) -> Vec<f64> {
+    let mut areas = Vec::new();
+    // Each remaining piece by its ends in order, with the integral of (x dy - y dx) / 2 from the first
+    // to the second, which summed around a loop is the area it encloses
+    let mut pieces = Vec::new();
+    for wall in walls {
+        match wall {
+            Wall::Segment(segment) => {
+                let (a, b) = (to_f64(segment.start), to_f64(segment.end));
+                pieces.push((a, b, 0.5 * (a.x * b.y - a.y * b.x)));
+            },
+            Wall::Arc(arc) if arc.is_circle() => areas.push(std::f64::consts::PI * (arc.radius as f64).powi(2)),
+            Wall::Arc(arc) => {
+                let (c, r) = (to_f64(arc.centre), arc.radius as f64);
+                let (start, sweep) = (arc.start_angle as f64, arc.sweep as f64);
+                let end = start + sweep;
+                let point = |angle: f64| Vector2::new(c.x + r * angle.cos(), c.y + r * angle.sin());
+                let integral = 0.5 * (r * r * sweep + r * (c.x * (end.sin() - start.sin()) - c.y * (end.cos() - start.cos())));
+                pieces.push((point(start), point(end), integral));
+            },
+            Wall::Plane(_) => (),
+        }
+    }
+
+    // Ends are taken to meet if they are as close as rounding in the walls' coordinates leaves them
+    let meet = |p: Vector2<f64>, q: Vector2<f64>| (p.x - q.x).hypot(p.y - q.y) <= 1e-4 * (1.0 + p.x.hypot(p.y));
+    while let Some((first, mut end, mut sum)) = pieces.pop() {
+        while !meet(end, first) {
+            let next = pieces.iter().position(|&(a, b, _)| meet(a, end) || meet(b, end));
+            match next.map(|i| pieces.swap_remove(i)) {
+                Some((a, b, integral)) if meet(a, end) => { end = b; sum += integral; },
+                Some((a, _, integral)) => { end = a; sum -= integral; },
+                None => break,
+            }
+        }
+        if meet(end, first) {
+            areas.push(sum.abs());
+        }
+    }
+    areas
+}
+
+
+// The area of the part of rect on the inner side of every plane among the walls
+pub fn area_within(rect: &Rect, walls: &[Wall]) -> f64 {
+    let (left, top) = (rect.left as f64, rect.top as f64);
+    let (right, bottom) = (left + rect.width as f64, top + rect.height as f64);
+    let mut polygon = vec![Vector2::new(left, top), Vector2::new(right, top), Vector2::new(right, bottom), Vector2::new(left, bottom)];
+    for plane in walls.iter().filter_map(|wall| match wall { Wall::Plane(plane) => Some(plane), _ => None }) {
+        let (position, normal) = (to_f64(plane.position), to_f64(plane.normal));
+        let side = |p: Vector2<f64>| (p.x - position.x) * normal.x + (p.y - position.y) * normal.y;
+        // Keep the corners on the inner side, and where the edges between them cross the plane
+        let mut clipped = Vec::new();
+        for i in 0..polygon.len() {
+            let (p, q) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+            if side(p) >= 0.0 {
+                clipped.push(p);
+            }
+            if (side(p) >= 0.0) != (side(q) >= 0.0) {
+                clipped.push(p + (q - p) * (side(p) / (side(p) - side(q))));
+            }
+        }
+        polygon = clipped;
+    }
+    let twice_area: f64 = (0..polygon.len())
+        .map(|i| {
+            let (p, q) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+            p.x * q.y - p.y * q.x
+        })
+        .sum();
+    0.5 * twice_area.abs()
+}
+
+
+fn to_f64(v: Vector2f) -> Vector2<f64> {
+    convert_vector2(v)
+}
+
+
+impl From<Plane> for Wall {
+    fn from(plane: Plane) -> Wall {
+        Wall::Plane(plane)
+    }
+}
+
+
+impl From<Segment> for Wall {
+    fn from(segment: Segment) -> Wall {
+        Wall::Segment(segment)
+    }
+}
+
+
+impl From<CircularArc> for Wall {
+    fn from(arc: CircularArc) -> Wall {
+        Wall::Arc(arc)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ball(x: f32, y: f32, vx: f32, vy: f32) -> Ball {
+        Ball::new(Vector2f{x, y}, Vector2f{x: vx, y: vy}, 1.0, 1.0)
+    }
+
+
+    #[test]
+    fn segment_faces_and_ends() {
+        let segment = Wall::from(Segment::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 10.0, y: 0.0}));
+        let none = Vector2f::default();
+        // Either face
+        assert_eq!(segment.collision_time(&ball(5.0, 5.0, 0.0, -2.0), none, false), Some(2.0));
+        assert_eq!(segment.collision_time(&ball(5.0, -5.0, 0.0, 2.0), none, false), Some(2.0));
+        // Head on into an end, along the segment's line
+        assert_eq!(segment.collision_time(&ball(15.0, 0.0, -2.0, 0.0), none, false), Some(2.0));
+        assert_eq!(segment.collision_time(&ball(-5.0, 0.0, 2.0, 0.0), none, false), Some(2.0));
+        // Past the end, missing the face but clipping the end 0.6 off centre
+        let t = segment.collision_time(&ball(10.6, 5.0, 0.0, -1.0), none, false).unwrap();
+        assert!((t - 4.2).abs() < 1e-5, "{}", t);
+        assert_eq!(segment.collision_time(&ball(11.5, 5.0, 0.0, -1.0), none, false), None);
+        // Touching an end and moving away
+        assert_eq!(segment.collision_time(&ball(11.0, 0.0, 1.0, 0.0), none, false), None);
+
+        // Bouncing off an end sends the ball away from it
+        let plane = segment.contact_plane(Vector2f{x: 10.6, y: 0.8});
+        assert!((plane.normal.x - 0.6).abs() < 1e-6 && (plane.normal.y - 0.8).abs() < 1e-6);
+        assert_eq!(plane.position, Vector2f{x: 10.0, y: 0.0});
+        // On the segment itself every normal is as good, but it must be one
+        let on = segment.contact_plane(Vector2f{x: 5.0, y: 0.0});
+        assert!(on.normal.x.abs() < 1e-6 && (on.normal.y.abs() - 1.0).abs() < 1e-6);
+        // Just below it, the plane faces down
+        let below = segment.contact_plane(Vector2f{x: 3.3, y: -1e-30});
+        assert_eq!(below.normal, Vector2f{x: 0.0, y: -1.0});
+    }
+
+
+    #[test]
+    fn point_on_an_arc_has_a_contact_normal() {
+        let circle = Wall::from(CircularArc::circle(Vector2f{x: 0.0, y: 0.0}, 5.0));
+        let plane = circle.contact_plane(Vector2f{x: 0.0, y: 5.0});
+        assert!(plane.normal.x.abs() < 1e-6 && (plane.normal.y.abs() - 1.0).abs() < 1e-6);
+        // Just inside it, the plane faces the centre
+        let inside = circle.contact_plane(Vector2f{x: 0.0, y: 4.999_999_5});
+        assert_eq!(inside.normal, Vector2f{x: 0.0, y: -1.0});
+        // At the centre, where every point of a whole circle is nearest, it faces the centre from one
+        let centre = circle.contact_plane(Vector2f{x: 0.0, y: 0.0});
+        assert_eq!(centre.normal, Vector2f{x: -1.0, y: 0.0});
+    }
+
+
+    #[test]
+    fn walls_parse_by_their_fields() {
+        let walls: Vec<Wall> = ron::from_str("[
+            (position: (x: 0.0, y: 0.0), normal: (x: 0.0, y: 1.0)),
+            (start: (x: 0.0, y: 0.0), end: (x: 10.0, y: 0.0), restitution: 0.5),
+            (centre: (x: 5.0, y: 5.0), radius: 3.0),
+            (centre: (x: 5.0, y: 5.0), radius: 3.0, start_angle: 1.0, sweep: 2.0, temperature: Some(10.0)),
+        ]").unwrap();
+        assert_eq!(walls[0], Wall::from(Plane::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 0.0, y: 1.0})));
+        assert_eq!(walls[1], Wall::from(Segment{restitution: 0.5, ..Segment::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 10.0, y: 0.0})}));
+        assert_eq!(walls[2], Wall::from(CircularArc::circle(Vector2f{x: 5.0, y: 5.0}, 3.0)));
+        assert!(matches!(walls[3], Wall::Arc(arc) if arc.sweep == 2.0 && arc.temperature == Some(10.0)));
+
+        let text = ron::to_string(&walls).unwrap();
+        assert_eq!(ron::from_str::<Vec<Wall>>(&text).unwrap(), walls);
+        // Neither a plane, a segment nor an arc
+        assert!(ron::from_str::<Wall>("(start: (x: 0.0, y: 0.0))").is_err());
+    }
+}
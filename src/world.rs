@@ -1,41 +1,137 @@
-use super::ball::Ball;
-use sfml::system::Vector2f;
-use sfml::graphics::{RenderWindow, RectangleShape, Transformable, Shape, Color, RenderTarget, FloatRect};
+use super::ball::{Ball, Impulse};
 use super::plane::Plane;
-use rand::Rng;
+use super::wall::{self, Wall};
+use super::obstacle::Obstacle;
+use super::geometry::Rect;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 use super::vector_math;
+use super::vector_math::{Vector2, Vector2f};
+use super::math::convert_vector2;
+use super::broad_phase::{BroadPhase, SpatialGrid};
+use super::scheduler::{Event, EventQueue};
+use std::sync::mpsc::{self, Receiver};
+use super::events::{CollisionEvent, Velocities, Subscribers, SubscriptionId};
+use super::restitution::{CombineRule, CollapseGuard};
+use super::field::Field;
+use super::potential::{self, Integrator, Potential};
+use super::periodic::Periodic;
+use super::diagnostics::{Baseline, Drift, ConservationCheck};
+use super::pressure::PressureGauge;
+use super::statistics::{self, EquationOfState};
+use super::time_series::TimeSeriesLog;
+use super::trajectory::TrajectoryRecorder;
+use super::lattice::Lattice;
 
 
 
 
 pub struct World {
     balls: Vec<Ball>,
-    walls: [Plane; 4],
+    walls: Vec<Wall>,
+    obstacles: Vec<Obstacle>,
+    broad_phase: BroadPhase,
+    collision_counts: Vec<u64>,
+    // The number of collisions of each kind resolved so far, indexed by CollisionKind
+    collision_totals: [u64; 3],
+    // Simulated time, which runs backwards along with dt
+    time: f64,
+    subscribers: Subscribers,
+    // The seed the world was generated from, if it is known
+    seed: Option<u64>,
+    restitution_rule: CombineRule,
+    collapse_guard: CollapseGuard,
+    friction_rule: CombineRule,
+    // See Ball::resolve_collision
+    tangential_restitution: f32,
+    // The time of each ball's most recent collision, for CollapseGuard::ContactDuration
+    last_collision: Vec<f64>,
+    field: Field,
+    integrator: Integrator,
+    periodic: Periodic,
+    // The work each wall has done on the balls, see get_wall_work
+    wall_work: Vec<f64>,
+    // The heat each thermal wall has given the balls, see get_wall_heat
+    wall_heat: Vec<f64>,
+    // Draws the velocities of balls leaving thermal walls. Seeded from the world's seed, or at random
+    // for unseeded worlds so that they don't all share one stream.
+    thermal_rng: StdRng,
+    // The total impulse given to the balls by walls, obstacles and the field
+    external_impulse: Vector2<f64>,
+    // The jumps in potential energy as balls wrap around periodic edges in a field
+    wrap_energy: f64,
+    // What drift is measured from, taken at the first update
+    baseline: Option<Baseline>,
+    conservation_check: ConservationCheck,
+    // Whether the drift has been logged as exceeding the tolerance, so that it is only logged once
+    drift_reported: bool,
+    pressure_gauge: PressureGauge,
+    log: Option<TimeSeriesLog>,
+    recorder: Option<TrajectoryRecorder>,
+    // The integer state of Integrator::Exact, rebuilt from the balls whenever they are changed
+    lattice: Option<Lattice>,
 }
 
 
 #[derive(Copy, Clone, Debug, PartialEq)]
-enum CollisionKind {
+pub enum CollisionKind {
     Wall,
     Ball,
+    Obstacle,
 }
 
 
 #[derive(Copy, Clone, Debug)]
-struct Collision {
-    kind: CollisionKind,
-    time: f32,
-    ball_index: usize,
-    collider_index: usize,
+pub struct Collision {
+    pub kind: CollisionKind,
+    // Time from when the collision was predicted until it happens, negative when running backwards
+    pub time: f32,
+    pub ball_index: usize,
+    pub collider_index: usize,
+}
+
+
+// The default scene with a randomly chosen seed
+impl Default for World {
+    fn default() -> World {
+        World::new(World::random_seed())
+    }
+}
+
+
+// Bookkeeping for a single call to update
+struct Step {
+    dt: f32,
+    queue: EventQueue,
+    // How far into the step each ball's position has been brought up to date
+    synced: Vec<f32>,
+    grid: Option<SpatialGrid>,
+    // The obstacles, which don't move, when using the grid broad phase
+    obstacle_grid: Option<SpatialGrid>,
+    // The velocity of each wall through the step. Walls are where they were at the start of the step
+    // until it ends.
+    wall_velocities: Vec<Vector2f>,
 }
 
 
 impl World {
-    pub fn new() -> World {
-        let walls_rect = FloatRect{left: 10.0, top: 150.0, width: 800.0,  height: 800.0};
+    // The default scene: 120 balls on a grid in an 800x800 box, moving in random directions.
+    // The same seed always gives the same world.
+    pub fn new(seed: u64) -> World {
+        let mut world = World::with_rng(&mut StdRng::seed_from_u64(seed));
+        world.set_seed(Some(seed));
+        world
+    }
+
 
-        let mut world = World{ balls: vec![Ball::default(); 120],
-            walls: World::rect_to_planes(&walls_rect)};
+    // The default scene, with the directions and speeds drawn from rng
+    pub fn with_rng<R: Rng>(rng: &mut R) -> World {
+        let walls_rect = Rect{left: 10.0, top: 150.0, width: 800.0,  height: 800.0};
+
+        let mut world = World::with_box(&walls_rect);
+        for _ in 0..120 {
+            world.add_ball(Ball::default());
+        }
 
         let offset = Ball::default().circle.radius * 3.0;
         let origin = Vector2f{x: walls_rect.left + offset, y: walls_rect.top + offset};
@@ -47,7 +143,6 @@ impl World {
             world.balls[i].circle.position = Vector2f{x, y} + origin;
         }
 
-        let mut rng = rand::thread_rng();
         let max_vel = 2000.0;
         for ball in &mut world.balls {
             let intensity = rng.gen_range(0.0, max_vel);
@@ -59,168 +154,1715 @@ impl World {
     }
 
 
+    // A world with no balls and no walls
+    pub fn empty() -> World {
+        World{balls: Vec::new(), walls: Vec::new(), obstacles: Vec::new(), broad_phase: BroadPhase::Grid{cell_size: None},
+              collision_counts: Vec::new(), collision_totals: [0; 3], time: 0.0, subscribers: Subscribers::new(), seed: None,
+              restitution_rule: CombineRule::default(), collapse_guard: CollapseGuard::default(),
+              friction_rule: CombineRule::default(), tangential_restitution: 0.0,
+              last_collision: Vec::new(), field: Field::None, integrator: Integrator::EventDriven,
+              periodic: Periodic::default(), wall_work: Vec::new(), wall_heat: Vec::new(),
+              thermal_rng: StdRng::seed_from_u64(World::random_seed()), external_impulse: Vector2::default(), wrap_energy: 0.0,
+              baseline: None,
+              conservation_check: ConservationCheck::Off, drift_reported: false, pressure_gauge: PressureGauge::new(1.0),
+              log: None, recorder: None, lattice: None}
+    }
+
+
+    // A world with no balls, enclosed by four walls around rect
+    pub fn with_box(rect: &Rect) -> World {
+        let mut world = World::empty();
+        for wall in World::rect_to_planes(rect).iter() {
+            world.add_wall(*wall);
+        }
+        world
+    }
+
+
+    // Steps the world on by dt, which is negative to run it backwards. With a log attached, a sample
+    // is taken at the end of each step during which one falls due.
+    pub fn update(&mut self, dt: f32) {
+        if dt == 0.0 {
+            return;
+        }
+        if let Some(recorder) = &mut self.recorder {
+            recorder.begin_update(&self.balls, dt);
+        }
+        match self.log.take() {
+            Some(log) => self.update_logged(log, dt),
+            None => self.advance(dt),
+        }
+        if let Some(mut recorder) = self.recorder.take() {
+            recorder.end_update(self);
+            self.recorder = Some(recorder);
+        }
+    }
+
+
+    fn update_logged(&mut self, mut log: TimeSeriesLog, dt: f32) {
+        let due = log.next_sample(self.time, dt);
+        self.advance(dt);
+        // The sample that fell due during the step, if any, is taken at its end, so that the log
+        // doesn't change how the world is stepped
+        if log.next_sample(self.time, dt) != due {
+            log.sample(self);
+        }
+        self.log = Some(log);
+    }
+
+
+    fn advance(&mut self, dt: f32) {
+        // Event-driven: predict every collision that could happen within dt and queue them. Then
+        // repeatedly take the soonest, advance only the balls involved to that time, resolve it and
+        // re-predict for just those balls. Predictions made before a ball's most recent collision are
+        // recognised by its collision counter and dropped. Every ball is brought up to date at the end.
+        if dt == 0.0 {
+            return;
+        }
+        if self.baseline.is_none() {
+            self.reset_drift();
+        }
+        match self.integrator {
+            Integrator::EventDriven => {},
+            Integrator::Verlet{potential, max_step} => {
+                self.update_verlet(dt, &potential, max_step);
+                self.pressure_gauge.end_update(self.time, dt.abs() as f64);
+                self.check_conservation();
+                return;
+            },
+            Integrator::Exact{potential, step, resolution} => {
+                self.update_exact(dt, &potential, step as f64, resolution as f64);
+                self.pressure_gauge.end_update(self.time, dt.abs() as f64);
+                self.check_conservation();
+                return;
+            },
+        }
 
-    pub fn update(&mut self, mut dt: f32) {
-        // A fully robust collision policy requires finding the soonest collision(s), advancing time to
-        // the point of collision(s), resolving the collisions and then repeating the process with re-computed velocities
+        let (grid, obstacle_grid) = match self.broad_phase {
+            BroadPhase::BruteForce => (None, None),
+            BroadPhase::Grid{cell_size} => {
+                let obstacles: Vec<Ball> = self.obstacles.iter().map(Obstacle::as_ball).collect();
+                (Some(SpatialGrid::from_balls(&self.balls, &self.field, &self.periodic, dt, cell_size)),
+                 Some(SpatialGrid::from_balls(&obstacles, &Field::None, &self.periodic, 0.0, None)))
+            },
+        };
+        let mut step = Step{dt, queue: EventQueue::new(), synced: vec![0.0; self.balls.len()], grid, obstacle_grid,
+                            wall_velocities: self.wall_velocities(dt)};
+        self.collision_counts.resize(self.balls.len(), 0);
 
-        while dt != 0.0 {
-            let soonest_collisions = self.get_soonest_collisions(dt < 0.0);
-            if soonest_collisions.len() > 0 {
-                if soonest_collisions[0].time.abs() > dt.abs() {
-                    break;  // No more collisions this frame
+        self.predict_all(&mut step);
+
+        while let Some(event) = step.queue.pop_valid(&self.collision_counts) {
+            let collision = event.collision;
+            let a = collision.ball_index;
+            let b = collision.collider_index;
+
+            self.advance_ball(&mut step, a, event.elapsed);
+            if collision.kind == CollisionKind::Ball {
+                self.advance_ball(&mut step, b, event.elapsed);
+            }
+
+            let time = self.time + (event.elapsed * dt.signum()) as f64;
+            let between = collision.kind == CollisionKind::Ball;
+            let before = Velocities::of(&self.balls[a]);
+            let collider_before = if between { Some(Velocities::of(&self.balls[b])) } else { None };
+            let impulse = self.resolve_collision(&step, &collision, time);
+            if let Some(recorder) = &mut self.recorder {
+                recorder.record_change(time, a, &self.balls[a]);
+                if collision.kind == CollisionKind::Ball {
+                    recorder.record_change(time, b, &self.balls[b]);
                 }
-                dt -= self.resolve_collisions(&soonest_collisions);
             }
-            else {
-                break;
+            if !self.subscribers.is_empty() {
+                let after = Velocities::of(&self.balls[a]);
+                self.subscribers.notify(&CollisionEvent{
+                    kind: collision.kind, ball_index: a, collider_index: b, time,
+                    impulse: impulse.vector(),
+                    before, after, collider_before,
+                    collider_after: if between { Some(Velocities::of(&self.balls[b])) } else { None },
+                });
             }
-        }
 
-        if dt != 0.0 {
-            for ball in &mut self.balls {
-                ball.displace(&(ball.velocity * dt));
+            self.collision_totals[collision.kind as usize] += 1;
+            self.collision_counts[a] += 1;
+            self.predict(&mut step, a, None);
+            if collision.kind == CollisionKind::Ball {
+                self.collision_counts[b] += 1;
+                self.predict(&mut step, b, Some(a));
             }
         }
+
+        for i in 0..self.balls.len() {
+            self.advance_ball(&mut step, i, dt.abs());
+        }
+        for (wall, velocity) in self.walls.iter_mut().zip(&step.wall_velocities) {
+            wall.translate(*velocity * dt);
+        }
+        let field_force = self.balls.iter()
+            .map(|b| convert_vector2(self.field.acceleration(b) * b.get_mass()))
+            .fold(Vector2::default(), |a, b| a + b);
+        self.external_impulse += field_force * dt as f64;
+        self.time += dt as f64;
+        self.pressure_gauge.end_update(self.time, dt.abs() as f64);
+        self.check_conservation();
     }
 
 
+    fn wall_velocities(&self, dt: f32) -> Vec<Vector2f> {
+        self.walls.iter().map(|wall| wall.motion().velocity(self.time, dt)).collect()
+    }
 
-    // Resolves the specified collisions, which should be concurrent. Advances simulation to
-    // the time of collision. Returns the elapsed time.
-    fn resolve_collisions(&mut self, collisions: &Vec<Collision>) -> f32 {
-        if collisions.len() == 0 { return 0.0; }
 
-        let t = collisions[0].time;
+    // Velocity Verlet, in as many equal steps as it takes for none to be longer than max_step
+    fn update_verlet(&mut self, dt: f32, potential: &Potential, max_step: f32) {
+        let steps = (dt.abs() / max_step).ceil().max(1.0);
+        let h = dt / steps;
+        let wall_velocities = self.wall_velocities(dt);
+        let mut accelerations = self.soft_accelerations(potential);
+        // The walls that move, with where they will be after each step
+        let moving: Vec<usize> = (0..self.walls.len()).filter(|&j| wall_velocities[j] != Vector2f::default()).collect();
+        for _ in 0..steps as usize {
+            let current: Vec<Wall> = moving.iter().map(|&j| self.walls[j]).collect();
+            let moved: Vec<Wall> = moving.iter().zip(&current)
+                .map(|(&j, &wall)| {
+                    let mut wall = wall;
+                    wall.translate(wall_velocities[j] * h);
+                    wall
+                })
+                .collect();
+            // The work a moving wall does is the energy its move adds to its interaction with the balls,
+            // averaged over where the balls are before and after the step
+            let work = |balls: &[Ball]| -> Vec<f64> {
+                moved.iter().zip(&current)
+                    .map(|(to, from)| potential::wall_energy(balls, to, potential) - potential::wall_energy(balls, from, potential))
+                    .collect()
+            };
+            let work_before = work(&self.balls);
 
-        // Advance the simulation to the point of the collision
-        for ball in &mut self.balls {
-            ball.displace(&(ball.velocity * t))
+            for (ball, a) in self.balls.iter_mut().zip(&accelerations) {
+                ball.velocity += *a * (0.5 * h);
+                ball.advance(h, Vector2f::default());
+            }
+            for i in 0..self.balls.len() {
+                self.wrap_ball(i);
+            }
+
+            let work_after = work(&self.balls);
+            for (n, &j) in moving.iter().enumerate() {
+                self.wall_work[j] += 0.5 * (work_before[n] + work_after[n]);
+                self.walls[j] = moved[n];
+            }
+            let previous = accelerations;
+            accelerations = self.soft_accelerations(potential);
+            for (ball, a) in self.balls.iter_mut().zip(&accelerations) {
+                ball.velocity += *a * (0.5 * h);
+            }
+            // The forces between balls cancel, leaving those from the walls, obstacles and field
+            let force = self.balls.iter().zip(previous.iter().zip(&accelerations))
+                .map(|(b, (&a0, &a1))| convert_vector2((a0 + a1) * (0.5 * b.get_mass())))
+                .fold(Vector2::default(), |a, b| a + b);
+            self.external_impulse += force * h as f64;
+            for (j, force) in potential::wall_forces(&self.balls, &self.walls, potential).iter().enumerate() {
+                self.pressure_gauge.add(j, force * h.abs() as f64);
+            }
+        }
+        self.time += dt as f64;
+    }
+
+
+    fn update_exact(&mut self, dt: f32, potential: &Potential, step: f64, resolution: f64) {
+        // Counting the multiples of step passed, rather than dividing up dt, means a run backwards
+        // through the same updates takes exactly as many steps as the run forwards
+        let end = self.time + dt as f64;
+        let steps = (end / step).round() as i64 - (self.time / step).round() as i64;
+        let mut lattice = self.take_lattice(step, resolution);
+        for _ in 0..steps.abs() {
+            if steps < 0 {
+                lattice.retreat_positions();
+                lattice.write(&mut self.balls);
+            }
+            let accelerations = self.soft_accelerations(potential);
+            if steps > 0 {
+                lattice.forward(&accelerations);
+            } else {
+                lattice.retreat_moves(&accelerations);
+            }
+            lattice.write(&mut self.balls);
+
+            let h = step * steps.signum() as f64;
+            let force = self.balls.iter().zip(&accelerations)
+                .map(|(b, &a)| convert_vector2(a * b.get_mass()))
+                .fold(Vector2::default(), |a, b| a + b);
+            self.external_impulse += force * h;
+            for (j, force) in potential::wall_forces(&self.balls, &self.walls, potential).iter().enumerate() {
+                self.pressure_gauge.add(j, force * step);
+            }
         }
+        self.time = end;
+        self.lattice = Some(lattice);
+    }
 
-        for collision in collisions {
-            self.resolve_collision(collision);
+
+    // The lattice for Integrator::Exact, made afresh from the balls if they have been changed
+    fn take_lattice(&mut self, step: f64, resolution: f64) -> Lattice {
+        match self.lattice.take() {
+            Some(lattice) if lattice.matches(&self.balls, step, resolution) => lattice,
+            _ => Lattice::new(&self.balls, step, resolution, &self.periodic),
         }
+    }
 
-        t
+
+    fn soft_accelerations(&self, potential: &Potential) -> Vec<Vector2f> {
+        let pairs = self.interacting_pairs(potential);
+        potential::accelerations(&self.balls, &self.walls, &self.obstacles, potential, &self.field, &self.periodic, &pairs)
     }
 
 
-    fn resolve_collision(&mut self, collision: &Collision) {
+    // Every pair of balls that might be within range of each other, from the broad phase
+    fn interacting_pairs(&self, potential: &Potential) -> Vec<(usize, usize)> {
+        match self.broad_phase {
+            BroadPhase::BruteForce => (0..self.balls.len())
+                .flat_map(|i| ((i + 1)..self.balls.len()).map(move |j| (i, j)))
+                .collect(),
+            BroadPhase::Grid{cell_size} => {
+                let max_radius = self.balls.iter().map(|b| b.circle.radius).fold(0.0, f32::max);
+                let cell_size = cell_size.unwrap_or_else(|| potential.range(2.0 * max_radius).max(1.0));
+                // The range is proportional to sigma, so boxes reaching half the range for a pair of
+                // identical balls overlap for any pair in range
+                let mut grid = SpatialGrid::with_periodic(cell_size, self.periodic);
+                for (i, ball) in self.balls.iter().enumerate() {
+                    let reach = 0.5 * potential.range(2.0 * ball.circle.radius);
+                    let p = ball.get_position();
+                    grid.insert(i, Vector2f{x: p.x - reach, y: p.y - reach}, Vector2f{x: p.x + reach, y: p.y + reach});
+                }
+                grid.pairs()
+            },
+        }
+    }
+
+
+    // Resolves a collision happening at the given time, with both bodies already at the point of
+    // contact. Returns the impulse ball a received.
+    fn resolve_collision(&mut self, step: &Step, collision: &Collision, time: f64) -> Impulse {
         let a = collision.ball_index;
         let b = collision.collider_index;
-        match collision.kind {
+        let impulse = match collision.kind {
             CollisionKind::Ball => {
-                let (va, vb) = Ball::resolve_collision(&self.balls[a], &self.balls[b]);
-                self.balls[a].velocity = va;
-                self.balls[b].velocity = vb;
+                // Across a periodic edge, b collides as the copy of itself next to a
+                let position_b = self.balls[b].get_position();
+                self.balls[b].circle.position = self.periodic.nearest_image(position_b, self.balls[a].get_position());
+
+                let since_last = (time - self.last_collision[a]).abs().min((time - self.last_collision[b]).abs());
+                let restitution = self.restitution_rule.combine(self.balls[a].get_restitution(), self.balls[b].get_restitution());
+                let restitution = self.collapse_guard.apply(restitution, Ball::normal_speed(&self.balls[a], &self.balls[b]), since_last);
+
+                let friction = self.friction_rule.combine(self.balls[a].get_friction(), self.balls[b].get_friction());
+
+                let (ball_a, ball_b) = pair_mut(&mut self.balls, a, b);
+                let impulse = Ball::resolve_collision(ball_a, ball_b, restitution, friction, self.tangential_restitution);
+                self.balls[b].circle.position = position_b;
+                self.last_collision[b] = time;
+                impulse
             },
             CollisionKind::Wall => {
-                self.balls[a].resolve_plane_collision(&self.walls[b]);
+                let wall = self.walls[b].contact_plane(self.balls[a].get_position());
+                let velocity = step.wall_velocities[b];
+                let energy = self.balls[a].kinetic_energy() as f64;
+                let impulse = self.bounce(a, &wall, velocity, time, step.dt < 0.0);
+                let work = vector_math::dot_product(&impulse.vector(), &velocity) as f64;
+                self.external_impulse += convert_vector2(impulse.vector());
+                self.pressure_gauge.add(b, impulse.normal as f64);
+                self.wall_work[b] += work;
+                // Whatever the ball gained other than by being pushed came from the heat bath
+                if wall.temperature.is_some() {
+                    self.wall_heat[b] += self.balls[a].kinetic_energy() as f64 - energy - work;
+                }
+                impulse
+            },
+            CollisionKind::Obstacle => {
+                let obstacle = &self.obstacles[b];
+                let wall = obstacle.contact_plane(self.periodic.nearest_image(self.balls[a].get_position(), obstacle.position));
+                let impulse = self.bounce(a, &wall, Vector2f::default(), time, step.dt < 0.0);
+                self.external_impulse += convert_vector2(impulse.vector());
+                impulse
+            },
+        };
+        self.last_collision[a] = time;
+        impulse
+    }
+
+
+    // Resolves ball a hitting something too heavy to be pushed back, given the plane it touches it along
+    // and the velocity that moves with. The collision is resolved in the frame moving with the wall.
+    // A thermal wall sends the ball off with a random velocity instead.
+    fn bounce(&mut self, a: usize, wall: &Plane, velocity: Vector2f, time: f64, invert_time: bool) -> Impulse {
+        let since_last = (time - self.last_collision[a]).abs();
+        let restitution = self.restitution_rule.combine(self.balls[a].get_restitution(), wall.restitution);
+        let normal_speed = vector_math::dot_product(&wall.normal, &(self.balls[a].velocity - velocity));
+        let restitution = self.collapse_guard.apply(restitution, normal_speed, since_last);
+
+        let friction = self.friction_rule.combine(self.balls[a].get_friction(), wall.friction);
+
+        let ball = &mut self.balls[a];
+        ball.velocity -= velocity;
+        let impulse = match wall.temperature {
+            Some(temperature) => ball.resolve_thermal_collision(wall, temperature, invert_time, &mut self.thermal_rng),
+            None => ball.resolve_plane_collision(wall, restitution, friction, self.tangential_restitution),
+        };
+        ball.velocity += velocity;
+        impulse
+    }
+
+
+    pub fn get_restitution_rule(&self) -> CombineRule {
+        self.restitution_rule
+    }
+
+
+    pub fn set_restitution_rule(&mut self, rule: CombineRule) {
+        self.restitution_rule = rule;
+    }
+
+
+    pub fn get_collapse_guard(&self) -> CollapseGuard {
+        self.collapse_guard
+    }
+
+
+    pub fn set_collapse_guard(&mut self, guard: CollapseGuard) {
+        self.collapse_guard = guard;
+    }
+
+
+    pub fn get_friction_rule(&self) -> CombineRule {
+        self.friction_rule
+    }
+
+
+    pub fn set_friction_rule(&mut self, rule: CombineRule) {
+        self.friction_rule = rule;
+    }
+
+
+    pub fn get_tangential_restitution(&self) -> f32 {
+        self.tangential_restitution
+    }
+
+
+    // Between -1 (smooth disks, no spin is exchanged) and 1 (perfectly rough disks). Coulomb friction
+    // limits how much of this is achieved, and with no friction disks are always smooth.
+    pub fn set_tangential_restitution(&mut self, beta: f32) {
+        self.tangential_restitution = beta;
+    }
+
+
+    // Translational plus rotational
+    pub fn kinetic_energy(&self) -> f64 {
+        self.balls.iter().map(|b| b.kinetic_energy() as f64).sum()
+    }
+
+
+    // The energy of the balls in the field, and of their interactions when they are soft. A field along a
+    // periodic axis has no consistent potential, and its share jumps as balls wrap around.
+    pub fn potential_energy(&self) -> f64 {
+        let field: f64 = self.balls.iter().map(|b| self.field.potential_energy(b) as f64).sum();
+        match self.integrator.potential() {
+            None => field,
+            Some(potential) => {
+                let pairs = self.interacting_pairs(&potential);
+                field + potential::interaction_energy(&self.balls, &self.walls, &self.obstacles, &potential, &self.periodic, &pairs)
+            },
+        }
+    }
+
+
+    pub fn total_energy(&self) -> f64 {
+        self.kinetic_energy() + self.potential_energy()
+    }
+
+
+    pub fn momentum(&self) -> Vector2<f64> {
+        self.balls.iter()
+            .map(|b| convert_vector2(b.velocity * b.get_mass()))
+            .fold(Vector2::default(), |a, b| a + b)
+    }
+
+
+    // Takes the current state as the baseline drift is measured from. This happens by itself at the
+    // first update, and after adding or removing balls; call it after any other change to the world.
+    pub fn reset_drift(&mut self) {
+        let input = self.energy_input();
+        let potential = self.potential_energy();
+        self.baseline = Some(Baseline{
+            time: self.time,
+            energy: self.kinetic_energy() + potential - input,
+            energy_scale: self.kinetic_energy() + potential.abs(),
+            momentum: self.momentum() - self.external_impulse,
+            momentum_scale: self.balls.iter()
+                .map(|b| vector_math::length_squared(&b.velocity).sqrt() as f64 * b.get_mass() as f64)
+                .sum(),
+        });
+        self.drift_reported = false;
+    }
+
+
+    pub fn get_baseline(&self) -> Option<Baseline> {
+        self.baseline
+    }
+
+
+    // The change in total energy and momentum since the baseline that walls, obstacles and the field
+    // don't account for, relative to their scale at the baseline. None before the first update.
+    pub fn drift(&self) -> Option<Drift> {
+        let baseline = self.baseline?;
+        let energy = self.total_energy() - self.energy_input() - baseline.energy;
+        let momentum = self.momentum() - self.external_impulse - baseline.momentum;
+        let relative = |change: f64, scale: f64| if scale > 0.0 { change / scale } else { change };
+        Some(Drift{energy: relative(energy, baseline.energy_scale),
+                   momentum: relative(momentum.x.hypot(momentum.y), baseline.momentum_scale)})
+    }
+
+
+    // Energy put in by moving and thermal walls, and by wrapping balls around in a field
+    fn energy_input(&self) -> f64 {
+        self.wall_work.iter().chain(&self.wall_heat).sum::<f64>() + self.wrap_energy
+    }
+
+
+    // The mean force per unit length on each wall over the pressure window, or None before the first
+    // update. Planes are taken to be as long as they are within the bounding rectangle, and a plane
+    // that doesn't cross it has a pressure of NaN.
+    pub fn wall_pressures(&self) -> Option<Vec<f64>> {
+        let rect = self.bounding_rect().unwrap_or_default();
+        let forces = self.pressure_gauge.forces()?;
+        Some(self.walls.iter().zip(forces)
+            .map(|(wall, force)| {
+                let length = wall.length_within(&rect) as f64;
+                if length > 0.0 { force / length } else { f64::NAN }
+            })
+            .collect())
+    }
+
+
+    // The pressure averaged over all the walls, or None if no wall has any length within the bounding
+    // rectangle, as in a world that is periodic on both axes
+    pub fn pressure(&self) -> Option<f64> {
+        let rect = self.bounding_rect().unwrap_or_default();
+        let forces = self.pressure_gauge.forces()?;
+        let (force, length) = self.walls.iter().zip(forces)
+            .map(|(wall, force)| (force, wall.length_within(&rect) as f64))
+            .filter(|&(_, length)| length > 0.0)
+            .fold((0.0, 0.0), |(f, l), (force, length)| (f + force, l + length));
+        if length > 0.0 { Some(force / length) } else { None }
+    }
+
+
+    // How much simulated time pressures are averaged over
+    pub fn get_pressure_window(&self) -> f64 {
+        self.pressure_gauge.get_window()
+    }
+
+
+    pub fn set_pressure_window(&mut self, window: f64) {
+        self.pressure_gauge.set_window(window);
+    }
+
+
+    // The space the balls move in, less the obstacles. Where there are planes or periodic edges, this
+    // is the bounding rectangle cut by any slanted planes, less whatever closed loops of segments and
+    // arcs lie within it. Otherwise it is the area inside the largest loop, less the others. None if
+    // nothing encloses the balls. Exact when the obstacles are clear of the walls and of each other.
+    pub fn area(&self) -> Option<f64> {
+        let loops = wall::loop_areas(&self.walls);
+        let inside_loops: f64 = loops.iter().sum();
+        let enclosed = if self.periodic.x || self.periodic.y || self.walls.iter().any(|w| matches!(w, Wall::Plane(_))) {
+            wall::area_within(&self.bounding_rect()?, &self.walls) - inside_loops
+        } else {
+            let outer = loops.iter().copied().fold(0.0, f64::max);
+            2.0 * outer - inside_loops
+        };
+        if enclosed <= 0.0 {
+            return None;
+        }
+        let obstacles: f64 = self.obstacles.iter().map(|o| std::f64::consts::PI * (o.radius as f64).powi(2)).sum();
+        Some(enclosed - obstacles)
+    }
+
+
+    // The fraction of the area covered by balls
+    pub fn packing_fraction(&self) -> Option<f64> {
+        let covered: f64 = self.balls.iter().map(|b| std::f64::consts::PI * (b.circle.radius as f64).powi(2)).sum();
+        Some(covered / self.area()?)
+    }
+
+
+    // The pressures expected at the current temperature, to compare with the measured one. None when
+    // the area isn't known.
+    pub fn predicted_pressure(&self) -> Option<EquationOfState> {
+        Some(EquationOfState::predict(self.balls.len(), self.area()?, statistics::temperature(&self.balls), self.packing_fraction()?))
+    }
+
+
+    pub fn get_conservation_check(&self) -> ConservationCheck {
+        self.conservation_check
+    }
+
+
+    pub fn set_conservation_check(&mut self, check: ConservationCheck) {
+        self.conservation_check = check;
+        self.drift_reported = false;
+    }
+
+
+    fn check_conservation(&mut self) {
+        let tolerance = match self.conservation_check.tolerance() {
+            Some(tolerance) => tolerance,
+            None => return,
+        };
+        let drift = match self.drift() {
+            Some(drift) => drift,
+            None => return,
+        };
+        if drift.largest() <= tolerance {
+            self.drift_reported = false;
+            return;
+        }
+        let message = format!("at time {:.4}, energy drift {:.3e} and momentum drift {:.3e} exceed the tolerance {:.1e}",
+                              self.time, drift.energy, drift.momentum, tolerance);
+        match self.conservation_check {
+            ConservationCheck::Panic{..} => panic!("{}", message),
+            _ if !self.drift_reported => {
+                eprintln!("warning: {}", message);
+                self.drift_reported = true;
+            },
+            _ => (),
+        }
+    }
+
+
+    pub fn get_integrator(&self) -> Integrator {
+        self.integrator
+    }
+
+
+    pub fn set_integrator(&mut self, integrator: Integrator) {
+        self.integrator = integrator;
+        self.lattice = None;
+    }
+
+
+    // Rounds the balls to the lattice of Integrator::Exact, which the next update would do anyway, so
+    // that their state can be compared with where they are after updates. Does nothing for other integrators.
+    pub fn quantize(&mut self) {
+        if let Integrator::Exact{step, resolution, ..} = self.integrator {
+            let mut lattice = self.take_lattice(step as f64, resolution as f64);
+            lattice.write(&mut self.balls);
+            self.lattice = Some(lattice);
+        }
+    }
+
+
+    // Turns every ball around, so that updating forwards retraces the path taken so far. With
+    // Integrator::Exact the path is retraced exactly, as for a Loschmidt echo. Updates take as many
+    // steps as multiples of the step they pass, so they only end where earlier ones did if dt is a
+    // multiple of the step.
+    pub fn reverse_velocities(&mut self) {
+        self.baseline = None;
+        if let Integrator::Exact{potential, step, resolution} = self.integrator {
+            let mut lattice = self.take_lattice(step as f64, resolution as f64);
+            lattice.reverse(&self.soft_accelerations(&potential));
+            lattice.write(&mut self.balls);
+            self.lattice = Some(lattice);
+            return;
+        }
+        for ball in &mut self.balls {
+            ball.velocity = -ball.velocity;
+            ball.angular_velocity = -ball.angular_velocity;
+        }
+    }
+
+
+    pub fn get_field(&self) -> Field {
+        self.field
+    }
+
+
+    pub fn set_field(&mut self, field: Field) {
+        self.field = field;
+    }
+
+
+    pub fn get_periodic(&self) -> Periodic {
+        self.periodic
+    }
+
+
+    // Balls outside the cell on a periodic axis are moved into it
+    pub fn set_periodic(&mut self, periodic: Periodic) {
+        self.periodic = periodic;
+        self.lattice = None;
+        for ball in &mut self.balls {
+            ball.circle.position = periodic.wrap(ball.get_position());
+        }
+    }
+
+
+    pub fn set_broad_phase(&mut self, broad_phase: BroadPhase) {
+        self.broad_phase = broad_phase;
+    }
+
+
+    pub fn get_broad_phase(&self) -> BroadPhase {
+        self.broad_phase
+    }
+
+
+    // The number of collisions each ball has been involved in
+    pub fn get_collision_counts(&self) -> &Vec<u64> {
+        &self.collision_counts
+    }
+
+
+    // The number of collisions of the given kind since the world was created
+    pub fn collision_total(&self, kind: CollisionKind) -> u64 {
+        self.collision_totals[kind as usize]
+    }
+
+
+    // Moves ball i along its free flight path to the given point in the step, wrapping it back into
+    // the cell if it crosses a periodic edge
+    fn advance_ball(&mut self, step: &mut Step, i: usize, elapsed: f32) {
+        self.balls[i] = self.ball_at(step, i, elapsed);
+        self.wrap_ball(i);
+        step.synced[i] = elapsed;
+    }
+
+
+    // Brings ball i back into the cell if it has crossed a periodic edge. A field along that axis has
+    // no consistent potential, so the jump in the ball's potential energy is counted as put in from outside.
+    fn wrap_ball(&mut self, i: usize) {
+        let ball = &mut self.balls[i];
+        let energy = self.field.potential_energy(ball) as f64;
+        ball.circle.position = self.periodic.wrap(ball.get_position());
+        self.wrap_energy += self.field.potential_energy(ball) as f64 - energy;
+    }
+
+
+    // A copy of ball i as it will be at the given point in the step, if it does not collide before then
+    fn ball_at(&self, step: &Step, i: usize, elapsed: f32) -> Ball {
+        let mut ball = self.balls[i];
+        let t = (elapsed - step.synced[i]) * step.dt.signum();
+        if t != 0.0 {
+            ball.advance(t, self.field.acceleration(&ball));
+        }
+        ball
+    }
+
+
+    // Queues every collision that could happen during the step, from the state at its start
+    fn predict_all(&self, step: &mut Step) {
+        // Candidate pairs (i, j) with i < j. Sorting them keeps the order in which predictions
+        // are made, and so the outcome, independent of the broad phase.
+        let pairs: Vec<(usize, usize)> = match &step.grid {
+            None => (0..self.balls.len())
+                .flat_map(|i| ((i + 1)..self.balls.len()).map(move |j| (i, j)))
+                .collect(),
+            Some(grid) => grid.pairs(),
+        };
+
+        for (i, j) in pairs {
+            self.predict_pair(step, i, j, 0.0);
+        }
+        for i in 0..self.balls.len() {
+            self.predict_walls(step, i, 0.0);
+            self.predict_obstacles(step, i, 0.0);
+        }
+    }
+
+
+    // Queues the collisions ball i could have from its last collision until the end of the step,
+    // other than with the ball given in skip
+    fn predict(&self, step: &mut Step, i: usize, skip: Option<usize>) {
+        let now = step.synced[i];
+        let candidates: Vec<usize> = match &mut step.grid {
+            None => (0..self.balls.len()).collect(),
+            Some(grid) => {
+                // The ball has a new velocity, so its swept box needs adding to the grid. Its old
+                // entries are left in place, which can only produce extra candidates.
+                let remaining = (step.dt.abs() - now) * step.dt.signum();
+                let ball = &self.balls[i];
+                let (min, max) = SpatialGrid::swept_bounds(ball, self.field.acceleration(ball), remaining);
+                grid.insert(i, min, max);
+                grid.query(min, max)
             }
         };
+
+        for j in candidates {
+            if j != i && Some(j) != skip {
+                self.predict_pair(step, i, j, now);
+            }
+        }
+        self.predict_walls(step, i, now);
+        self.predict_obstacles(step, i, now);
     }
 
 
-    // Excludes collisions at time 0 as these should have been resolved in the previous iteration
-    fn get_soonest_collisions(&self, invert_time: bool) -> Vec<Collision> {
-        let mut soonest_collisions: Vec<Collision> = Vec::new();
+    // Queues the collision between balls i and j if it happens before the end of the step.
+    // Both are considered at time now. A pair that has just collided is moving apart, so it isn't
+    // predicted to collide again; one touching and approaching collides at once.
+    fn predict_pair(&self, step: &mut Step, i: usize, j: usize, now: f32) {
+        let a = self.ball_at(step, i, now);
+        let mut b = self.ball_at(step, j, now);
+        b.circle.position = self.periodic.nearest_image(b.get_position(), a.get_position());
+        let (accel_a, accel_b) = (self.field.acceleration(&a), self.field.acceleration(&b));
+        if let Some(t) = Ball::collision_time(&a, &b, accel_a, accel_b, step.dt < 0.0) {
+            let collision = Collision{kind: CollisionKind::Ball, time: t, ball_index: i, collider_index: j};
+            self.schedule(step, collision, now);
+        }
+    }
 
-        let mut add_collision_perhaps = |new_collision: &Collision| {
-            if new_collision.time == 0.0 { return; }
 
-            if soonest_collisions.len() > 0 {
-                if new_collision.time.abs() < soonest_collisions[0].time.abs() {
-                    soonest_collisions.clear();
-                    soonest_collisions.push(*new_collision);
-                }
-                else if new_collision.time == soonest_collisions[0].time {
-                    soonest_collisions.push(*new_collision);
-                }
+    // Moving walls are brought to time now, and the ball considered in the frame moving with them
+    fn predict_walls(&self, step: &mut Step, i: usize, now: f32) {
+        let ball = self.ball_at(step, i, now);
+        let acceleration = self.field.acceleration(&ball);
+        for j in 0..self.walls.len() {
+            let velocity = step.wall_velocities[j];
+            let mut wall = self.walls[j];
+            let mut ball = ball;
+            if velocity != Vector2f::default() {
+                wall.translate(velocity * (now * step.dt.signum()));
+                ball.velocity -= velocity;
             }
-            else {
-                soonest_collisions.push(*new_collision);
+            if let Some(t) = wall.collision_time(&ball, acceleration, step.dt < 0.0) {
+                let collision = Collision{kind: CollisionKind::Wall, time: t, ball_index: i, collider_index: j};
+                self.schedule(step, collision, now);
             }
+        }
+    }
+
+
+    fn predict_obstacles(&self, step: &mut Step, i: usize, now: f32) {
+        let ball = self.ball_at(step, i, now);
+        let acceleration = self.field.acceleration(&ball);
+        let candidates: Vec<usize> = match &step.obstacle_grid {
+            None => (0..self.obstacles.len()).collect(),
+            Some(grid) => {
+                let remaining = (step.dt.abs() - now) * step.dt.signum();
+                let (min, max) = SpatialGrid::swept_bounds(&ball, acceleration, remaining);
+                grid.query(min, max)
+            },
         };
+        for j in candidates {
+            let obstacle = self.obstacles[j].as_ball();
+            let mut ball = ball;
+            ball.circle.position = self.periodic.nearest_image(ball.get_position(), obstacle.get_position());
+            if let Some(t) = Ball::collision_time(&ball, &obstacle, acceleration, Vector2f::default(), step.dt < 0.0) {
+                let collision = Collision{kind: CollisionKind::Obstacle, time: t, ball_index: i, collider_index: j};
+                self.schedule(step, collision, now);
+            }
+        }
+    }
 
 
-        for i in 0..self.balls.len() {
-            for j in (i + 1)..self.balls.len() {
-                if let Some(t) = Ball::collision_time(&self.balls[i], &self.balls[j], invert_time) {
-                    add_collision_perhaps(&Collision{kind: CollisionKind::Ball, time: t, ball_index: i, collider_index: j});
+    fn schedule(&self, step: &mut Step, collision: Collision, now: f32) {
+        let elapsed = now + collision.time.abs();
+        if elapsed > step.dt.abs() {
+            return;
+        }
+        let collider_count = match collision.kind {
+            CollisionKind::Ball => self.collision_counts[collision.collider_index],
+            CollisionKind::Wall | CollisionKind::Obstacle => 0,
+        };
+        step.queue.push(Event{elapsed, collision, ball_count: self.collision_counts[collision.ball_index], collider_count});
+    }
+
+
+
+    pub fn get_balls(&self) -> &Vec<Ball>{
+        &self.balls
+    }
+
+
+    pub fn get_walls(&self) -> &[Wall] {
+        &self.walls
+    }
+
+
+    pub fn get_ball(&self, index: usize) -> Option<&Ball> {
+        self.balls.get(index)
+    }
+
+
+    pub fn get_ball_mut(&mut self, index: usize) -> Option<&mut Ball> {
+        self.balls.get_mut(index)
+    }
+
+
+    // Simulated time since the world was created
+    pub fn get_time(&self) -> f64 {
+        self.time
+    }
+
+
+    // Only for playback, which sets the state of the world directly
+    pub(crate) fn set_time(&mut self, time: f64) {
+        self.time = time;
+    }
+
+
+    // Records the trajectory of every ball as the world is updated, replacing any recorder already
+    // attached. See TrajectoryRecorder.
+    pub fn set_recorder(&mut self, recorder: TrajectoryRecorder) -> Option<TrajectoryRecorder> {
+        self.recorder.replace(recorder)
+    }
+
+
+    // Detaches the recorder, for example to finish it at the end of a run
+    pub fn take_recorder(&mut self) -> Option<TrajectoryRecorder> {
+        self.recorder.take()
+    }
+
+
+    // Samples observables into log as the world is updated, replacing any log already attached
+    pub fn set_log(&mut self, log: TimeSeriesLog) -> Option<TimeSeriesLog> {
+        self.log.replace(log)
+    }
+
+
+    // Detaches the log, for example to finish it at the end of a run
+    pub fn take_log(&mut self) -> Option<TimeSeriesLog> {
+        self.log.take()
+    }
+
+
+    pub fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+
+    // Records the seed the world was generated from, for worlds not built by World::new. This also
+    // reseeds the draws made by thermal walls.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+        self.thermal_rng = StdRng::seed_from_u64(seed.unwrap_or_else(World::random_seed));
+    }
+
+
+    pub fn random_seed() -> u64 {
+        rand::thread_rng().gen()
+    }
+
+
+    // Returns the index of the new ball
+    pub fn add_ball(&mut self, ball: Ball) -> usize {
+        self.balls.push(ball);
+        self.baseline = None;
+        self.collision_counts.push(0);
+        self.last_collision.push(f64::NEG_INFINITY);
+        self.balls.len() - 1
+    }
+
+
+    // Balls after the removed one move down an index
+    pub fn remove_ball(&mut self, index: usize) -> Ball {
+        self.baseline = None;
+        self.collision_counts.remove(index);
+        self.last_collision.remove(index);
+        self.balls.remove(index)
+    }
+
+
+    // Returns the index of the new wall
+    pub fn add_wall<W: Into<Wall>>(&mut self, wall: W) -> usize {
+        self.walls.push(wall.into());
+        self.wall_work.push(0.0);
+        self.wall_heat.push(0.0);
+        self.pressure_gauge.reset(self.walls.len());
+        self.walls.len() - 1
+    }
+
+
+    // Walls after the removed one move down an index
+    pub fn remove_wall(&mut self, index: usize) -> Wall {
+        self.wall_work.remove(index);
+        self.wall_heat.remove(index);
+        let wall = self.walls.remove(index);
+        self.pressure_gauge.reset(self.walls.len());
+        wall
+    }
+
+
+    // Replaces a wall, for example to change how it moves, keeping its work done so far
+    pub fn set_wall<W: Into<Wall>>(&mut self, index: usize, wall: W) {
+        self.walls[index] = wall.into();
+    }
+
+
+    // The work each wall has done on the balls so far, positive where it has given them energy.
+    // Only moving walls do any: pushing a piston in heats the gas and pulling it out cools it.
+    pub fn get_wall_work(&self) -> &[f64] {
+        &self.wall_work
+    }
+
+
+    // The heat each thermal wall has passed to the balls so far, negative where it has taken it from
+    // them. Thermal walls only act on hard balls; soft balls feel them as ordinary walls.
+    pub fn get_wall_heat(&self) -> &[f64] {
+        &self.wall_heat
+    }
+
+
+    pub fn get_obstacles(&self) -> &[Obstacle] {
+        &self.obstacles
+    }
+
+
+    // Returns the index of the new obstacle
+    pub fn add_obstacle(&mut self, obstacle: Obstacle) -> usize {
+        self.obstacles.push(obstacle);
+        self.obstacles.len() - 1
+    }
+
+
+    // Obstacles after the removed one move down an index
+    pub fn remove_obstacle(&mut self, index: usize) -> Obstacle {
+        self.obstacles.remove(index)
+    }
+
+
+    // Calls callback with every collision from now on, in the order they are resolved
+    pub fn subscribe<F: FnMut(&CollisionEvent) + 'static>(&mut self, callback: F) -> SubscriptionId {
+        self.subscribers.add(Box::new(callback))
+    }
+
+
+    // Sends every collision from now on down a channel, for handling on another thread or reading
+    // back after an update with Receiver::try_iter
+    pub fn subscribe_channel(&mut self) -> (SubscriptionId, Receiver<CollisionEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        (self.subscribers.add_channel(sender), receiver)
+    }
+
+
+    // Returns false if there was no such subscription
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.subscribers.remove(id)
+    }
+
+
+    // The rectangle the balls are contained in. On each side this is the edge of the cell if the
+    // axis is periodic, else the innermost axis-aligned plane facing in from that side or, if there
+    // isn't one, the furthest extent of the other walls. None if some side has none of these.
+    pub fn bounding_rect(&self) -> Option<Rect> {
+        let plane_side = |normal: Vector2f, coordinate: fn(Vector2f) -> f32, innermost: fn(f32, f32) -> f32| {
+            self.walls.iter()
+                .filter_map(|wall| match wall { Wall::Plane(plane) if plane.normal == normal => Some(plane), _ => None })
+                .map(|plane| coordinate(plane.position))
+                .reduce(innermost)
+        };
+        let extent_side = |coordinate: fn(Vector2f) -> f32, outermost: fn(f32, f32) -> f32| {
+            self.walls.iter()
+                .filter_map(Wall::extent)
+                .flat_map(|(min, max)| [min, max])
+                .map(coordinate)
+                .reduce(outermost)
+        };
+        let cell = &self.periodic.cell;
+        let (left, right) = if self.periodic.x {
+            (cell.left, cell.left + cell.width)
+        } else {
+            (plane_side(Vector2f{x: 1.0, y: 0.0},  |p| p.x, f32::max).or_else(|| extent_side(|p| p.x, f32::min))?,
+             plane_side(Vector2f{x: -1.0, y: 0.0}, |p| p.x, f32::min).or_else(|| extent_side(|p| p.x, f32::max))?)
+        };
+        let (top, bottom) = if self.periodic.y {
+            (cell.top, cell.top + cell.height)
+        } else {
+            (plane_side(Vector2f{x: 0.0, y: 1.0},  |p| p.y, f32::max).or_else(|| extent_side(|p| p.y, f32::min))?,
+             plane_side(Vector2f{x: 0.0, y: -1.0}, |p| p.y, f32::min).or_else(|| extent_side(|p| p.y, f32::max))?)
+        };
+        Some(Rect{left, top, width: right - left, height: bottom - top})
+    }
+
+
+    pub fn rect_to_planes(rect: &Rect) -> [Plane; 4] {
+        [
+            Plane::new(Vector2f{x: rect.left, y: 0.0}, Vector2f{x: 1.0, y: 0.0}),
+            Plane::new(Vector2f{x: rect.left + rect.width, y: 0.0}, Vector2f{x: -1.0, y: 0.0}),
+            Plane::new(Vector2f{x: 0.0, y: rect.top}, Vector2f{x: 0.0, y: 1.0}),
+            Plane::new(Vector2f{x: 0.0, y: rect.top + rect.height}, Vector2f{x: 0.0, y: -1.0})
+        ]
+    }
+
+
+}
+
+
+// Mutable references to two different elements of a slice
+fn pair_mut<T>(items: &mut [T], a: usize, b: usize) -> (&mut T, &mut T) {
+    assert!(a != b);
+    if a < b {
+        let (left, right) = items.split_at_mut(b);
+        (&mut left[a], &mut right[0])
+    } else {
+        let (left, right) = items.split_at_mut(a);
+        (&mut right[0], &mut left[b])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{SeedableRng, rngs::StdRng};
+    use crate::wall::Segment;
+
+    // A box full of non-overlapping balls with random sizes, positions and velocities
+    fn random_world(seed: u64, count: usize) -> World {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let walls_rect = Rect{left: 0.0, top: 0.0, width: 1000.0, height: 1000.0};
+        let mut world = World::with_box(&walls_rect);
+        world.set_broad_phase(BroadPhase::BruteForce);
+
+        while world.balls.len() < count {
+            let mut ball = Ball::default();
+            ball.circle.radius = rng.gen_range(2.0, 12.0);
+            let r = ball.circle.radius;
+            ball.set_position(rng.gen_range(r, walls_rect.width - r), rng.gen_range(r, walls_rect.height - r));
+            if world.balls.iter().any(|b| b.intersects(&ball)) {
+                continue;
+            }
+            ball.velocity = Vector2f{x: rng.gen_range(-500.0, 500.0), y: rng.gen_range(-500.0, 500.0)};
+            world.add_ball(ball);
+        }
+        world
+    }
+
+
+    #[test]
+    fn grid_broad_phase_matches_brute_force() {
+        for seed in 0..10 {
+            let mut brute = random_world(seed, 200);
+            let mut grid = random_world(seed, 200);
+            grid.set_broad_phase(BroadPhase::Grid{cell_size: if seed % 2 == 0 { None } else { Some(25.0) }});
+
+            for &dt in &[1.0 / 60.0; 10] {
+                brute.update(dt);
+                grid.update(dt);
+            }
+            for &dt in &[-1.0 / 60.0; 5] {
+                brute.update(dt);
+                grid.update(dt);
+            }
+            assert!(brute.get_collision_counts().iter().sum::<u64>() > 0);
+            assert_eq!(brute.get_collision_counts(), grid.get_collision_counts());
+            for (a, b) in brute.get_balls().iter().zip(grid.get_balls()) {
+                assert_eq!(a.get_position(), b.get_position());
+                assert_eq!(a.velocity, b.velocity);
+            }
+        }
+    }
+
+
+    #[test]
+    fn same_seed_gives_same_simulation() {
+        let mut a = World::new(7);
+        let mut b = World::new(7);
+        for &dt in &[1.0 / 60.0, 1.0 / 60.0, 0.05, -1.0 / 30.0, 1.0 / 60.0] {
+            a.update(dt);
+            b.update(dt);
+        }
+        for (x, y) in a.get_balls().iter().zip(b.get_balls()) {
+            assert_eq!(x.get_position(), y.get_position());
+            assert_eq!(x.velocity, y.velocity);
+        }
+        // The balls start on a grid, so only their velocities depend on the seed
+        let (seven, eight) = (World::new(7), World::new(8));
+        assert!(seven.get_balls().iter().zip(eight.get_balls()).all(|(x, y)| x.velocity != y.velocity));
+    }
+
+
+    // Panics if any two balls overlap, or any ball overlaps the walls of the box, by more than
+    // rounding error
+    fn assert_separated(world: &World, size: f32) {
+        let tolerance = 1e-3;
+        for (i, a) in world.balls.iter().enumerate() {
+            let (p, r) = (a.get_position(), a.circle.radius);
+            assert!(p.x > r - tolerance && p.x < size - r + tolerance && p.y > r - tolerance && p.y < size - r + tolerance,
+                    "ball {} at {:?} escaped at {}", i, p, world.time);
+            for (j, b) in world.balls.iter().enumerate().skip(i + 1) {
+                let distance = vector_math::length_squared(&(p - b.get_position())).sqrt();
+                assert!(distance > r + b.circle.radius - tolerance, "balls {} and {} overlap at {}", i, j, world.time);
+            }
+        }
+    }
+
+
+    #[test]
+    fn balls_never_overlap_or_escape() {
+        for seed in 0..20 {
+            let mut world = random_world(100 + seed, 150);
+            let energy = world.total_energy();
+            for _ in 0..60 {
+                world.update(1.0 / 60.0);
+                assert_separated(&world, 1000.0);
+            }
+            assert!(((world.total_energy() - energy) / energy).abs() < 1e-4);
+        }
+    }
+
+
+    #[test]
+    fn newtons_cradle_passes_momentum_along_touching_balls() {
+        let mut world = World::with_box(&Rect{left: 0.0, top: 0.0, width: 1000.0, height: 1000.0});
+        world.add_ball(Ball::new(Vector2f{x: 50.0, y: 500.0}, Vector2f{x: 100.0, y: 0.0}, 10.0, 1.0));
+        for k in 0..4 {
+            world.add_ball(Ball::new(Vector2f{x: 100.0 + 20.0 * k as f32, y: 500.0}, Vector2f::default(), 10.0, 1.0));
+        }
+        world.update(0.4);
+        assert_separated(&world, 1000.0);
+        for ball in &world.balls[..4] {
+            assert!(ball.velocity.x.abs() < 1e-3, "{:?}", ball.velocity);
+        }
+        assert!((world.balls[4].velocity.x - 100.0).abs() < 1e-3);
+    }
+
+
+    #[test]
+    fn simultaneous_collisions_conserve_energy_and_momentum() {
+        for &count in &[2, 3, 4, 6] {
+            let mut world = World::with_box(&Rect{left: 0.0, top: 0.0, width: 1000.0, height: 1000.0});
+            let centre = Vector2f{x: 500.0, y: 500.0};
+            for k in 0..count {
+                let direction = vector_math::rotate(&Vector2f{x: 1.0, y: 0.0}, 2.0 * std::f32::consts::PI * k as f32 / count as f32);
+                world.add_ball(Ball::new(centre + direction * 100.0, direction * -100.0, 10.0, 1.0));
+            }
+            let energy = world.total_energy();
+            for _ in 0..120 {
+                world.update(1.0 / 60.0);
+                assert_separated(&world, 1000.0);
+            }
+            assert!(((world.total_energy() - energy) / energy).abs() < 1e-5);
+            let momentum = world.momentum();
+            assert!(momentum.x.abs() < 1e-2 && momentum.y.abs() < 1e-2, "{:?}", momentum);
+        }
+    }
+
+
+    #[test]
+    fn ball_falls_onto_the_floor_when_predicted() {
+        // From rest, gravity g closes a gap h in sqrt(2h / g)
+        let mut world = World::with_box(&Rect{left: 0.0, top: 0.0, width: 1000.0, height: 1000.0});
+        world.set_field(Field::Gravity(Vector2f{x: 0.0, y: 100.0}));
+        world.add_ball(Ball::new(Vector2f{x: 500.0, y: 500.0}, Vector2f{x: 30.0, y: 0.0}, 10.0, 1.0));
+        let hit = (2.0 * 490.0 / 100.0f64).sqrt();
+
+        world.update((hit - 1e-3) as f32);
+        assert_eq!(world.collision_total(CollisionKind::Wall), 0);
+        assert!((world.balls[0].get_position().y as f64 - (990.0 - 0.5 * 100.0 * 1e-3 * (2.0 * hit - 1e-3))).abs() < 1e-2);
+
+        world.update(2e-3);
+        assert_eq!(world.collision_total(CollisionKind::Wall), 1);
+        // Bounced at speed g * hit, then slowed by gravity for the rest of the step
+        let expected = -100.0 * hit + 100.0 * (world.time - hit);
+        assert!((world.balls[0].velocity.y as f64 - expected).abs() < 1e-2, "{} for {}", world.balls[0].velocity.y, expected);
+        assert_eq!(world.balls[0].velocity.x, 30.0);
+    }
+
+
+    #[test]
+    fn verlet_conserves_energy_with_wca() {
+        let scene = crate::scene::Scene::parse("(seed: Some(5), container: Box((left: 0.0, top: 0.0, width: 400.0, height: 400.0)),
+            spawn: [(count: 60, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 8.0, mass: 1.0,
+                     speed: Uniform(min: 50.0, max: 300.0))],
+            integrator: Verlet(potential: Wca(epsilon: 2000.0), max_step: 0.0005))").unwrap();
+        let mut world = scene.build().unwrap();
+        let energy = world.total_energy();
+        let mut worst: f64 = 0.0;
+        for _ in 0..120 {
+            world.update(1.0 / 60.0);
+            worst = worst.max(((world.total_energy() - energy) / energy).abs());
+        }
+        assert!(worst < 1e-3, "energy drifted by {}", worst);
+    }
+
+
+    #[test]
+    fn balls_bounce_out_of_polygon_corners() {
+        // Into the corners of a square and a triangle, head on and off to either side
+        let square = [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)];
+        let triangle = [(0.0, 0.0), (100.0, 0.0), (50.0, 80.0)];
+        for vertices in [&square[..], &triangle[..]] {
+            let vertices: Vec<Vector2f> = vertices.iter().map(|&(x, y)| Vector2f{x, y}).collect();
+            let centre = vertices.iter().fold(Vector2f::default(), |a, &v| a + v) / vertices.len() as f32;
+            for &corner in &vertices {
+                for &skew in &[0.0, 0.05, -0.05] {
+                    let mut world = World::empty();
+                    for segment in Segment::polygon(&vertices) {
+                        world.add_wall(segment);
+                    }
+                    let direction = vector_math::rotate(&(corner - centre), skew);
+                    world.add_ball(Ball::new(centre, direction * 2.0, 3.0, 1.0));
+                    for _ in 0..60 {
+                        world.update(1.0 / 30.0);
+                        let p = world.balls[0].get_position();
+                        let clear = world.walls.iter().all(|w| w.distance(p) > 3.0 - 1e-3);
+                        let within = vertices.iter().any(|v| v.x < p.x) && vertices.iter().any(|v| v.x > p.x)
+                            && vertices.iter().any(|v| v.y < p.y) && vertices.iter().any(|v| v.y > p.y);
+                        assert!(clear && within, "escaped through corner {:?} at {:?}", corner, p);
+                    }
+                    assert!(world.collision_total(CollisionKind::Wall) >= 2);
+                    let speed = vector_math::length_squared(&world.balls[0].velocity).sqrt();
+                    assert!((speed - 2.0 * vector_math::length_squared(&(corner - centre)).sqrt()).abs() < 1e-3);
                 }
             }
+        }
+    }
+
 
-            for j in 0..self.walls.len()  {
-                if let Some(t) = self.balls[i].plane_collision_time(&self.walls[j], invert_time) {
-                    add_collision_perhaps(&Collision{kind: CollisionKind::Wall, time: t, ball_index: i, collider_index: j});
+    #[test]
+    fn balls_stay_inside_a_circle() {
+        for seed in 0..5 {
+            let scene = crate::scene::Scene::parse(&format!("(seed: Some({}), container: Circle(centre: (x: 0.0, y: 0.0), radius: 300.0),
+                spawn: [(count: 80, region: (left: -200.0, top: -200.0, width: 400.0, height: 400.0), radius: 6.0, mass: 1.0,
+                         speed: Uniform(min: 100.0, max: 800.0))])", seed)).unwrap();
+            let mut world = scene.build().unwrap();
+            for _ in 0..120 {
+                world.update(1.0 / 60.0);
+                for ball in &world.balls {
+                    let distance = vector_math::length_squared(&ball.get_position()).sqrt();
+                    assert!(distance < 300.0 - ball.circle.radius + 1e-3, "escaped to {:?}", ball.get_position());
                 }
             }
+            assert!(world.collision_total(CollisionKind::Wall) > 100);
         }
+    }
+
 
-        if soonest_collisions.len() > 1 {
-            println!("soonest_collisions.len(): {}", soonest_collisions.len());
+    #[test]
+    fn balls_reflect_off_obstacles() {
+        // Head on, and at 30 degrees to the normal at the point of contact
+        let offset = 15.0 * (std::f32::consts::PI / 6.0).sin();
+        for &(y, reflected) in &[(500.0, Vector2f{x: -100.0, y: 0.0}),
+                                 (500.0 - offset, Vector2f{x: -50.0, y: -100.0 * 0.75f32.sqrt()})] {
+            let mut world = World::with_box(&Rect{left: 0.0, top: 0.0, width: 1000.0, height: 1000.0});
+            world.add_obstacle(Obstacle::new(Vector2f{x: 500.0, y: 500.0}, 10.0));
+            world.add_ball(Ball::new(Vector2f{x: 300.0, y}, Vector2f{x: 100.0, y: 0.0}, 5.0, 1.0));
+            world.update(2.0);
+            assert_eq!(world.collision_total(CollisionKind::Obstacle), 1);
+            let velocity = world.balls[0].velocity;
+            assert!(vector_math::length_squared(&(velocity - reflected)).sqrt() < 1e-3, "{:?} for {:?}", velocity, reflected);
         }
+    }
+
 
-        soonest_collisions
+    #[test]
+    fn balls_collide_across_periodic_edges() {
+        // Both broad phases, across the left and right edges and through a corner
+        for &broad_phase in &[BroadPhase::BruteForce, BroadPhase::Grid{cell_size: None}] {
+            for &(start, velocity) in &[(Vector2f{x: 990.0, y: 500.0}, Vector2f{x: 50.0, y: 0.0}),
+                                        (Vector2f{x: 990.0, y: 990.0}, Vector2f{x: 50.0, y: 50.0})] {
+                let cell = Rect{left: 0.0, top: 0.0, width: 1000.0, height: 1000.0};
+                let mut world = World::empty();
+                world.set_broad_phase(broad_phase);
+                world.set_periodic(Periodic::new(cell, true, true));
+                let across = Vector2f{x: 1000.0 - start.x, y: if velocity.y == 0.0 { start.y } else { 1000.0 - start.y }};
+                world.add_ball(Ball::new(start, velocity, 5.0, 1.0));
+                world.add_ball(Ball::new(across, -velocity, 5.0, 1.0));
+
+                world.update(0.2);
+                assert_eq!(world.collision_total(CollisionKind::Ball), 1);
+                // Equal masses head on swap velocities
+                assert!(vector_math::length_squared(&(world.balls[0].velocity + velocity)).sqrt() < 1e-3);
+                assert!(vector_math::length_squared(&(world.balls[1].velocity - velocity)).sqrt() < 1e-3);
+                // and move apart, staying in the cell
+                let (a, b) = (world.balls[0].get_position(), world.balls[1].get_position());
+                assert!(world.periodic.wrap(a) == a && world.periodic.wrap(b) == b);
+                assert!(vector_math::length_squared(&world.periodic.minimum_image(a - b)).sqrt() > 10.0);
+            }
+        }
     }
 
 
+    #[test]
+    fn piston_compression_heats_the_gas() {
+        let scene = crate::scene::Scene::parse("(seed: Some(9), container: Walls([
+               (position: (x: 0.0, y: 0.0), normal: (x: 1.0, y: 0.0)),
+               (position: (x: 800.0, y: 0.0), normal: (x: -1.0, y: 0.0), motion: (velocity: (x: -60.0, y: 0.0))),
+               (position: (x: 0.0, y: 0.0), normal: (x: 0.0, y: 1.0)),
+               (position: (x: 0.0, y: 400.0), normal: (x: 0.0, y: -1.0)),
+            ]),
+            spawn: [(count: 100, region: (left: 0.0, top: 0.0, width: 800.0, height: 400.0), radius: 5.0, mass: 1.0,
+                     speed: MaxwellBoltzmann(temperature: 10000.0))])").unwrap();
+        let mut world = scene.build().unwrap();
+        let energy = world.kinetic_energy();
+        for _ in 0..300 {
+            world.update(1.0 / 60.0);
+        }
+        let gained = world.kinetic_energy() - energy;
+        assert!(gained > 0.1 * energy, "gained only {}", gained);
+        // All of it was work done by the piston
+        assert!((world.get_wall_work()[1] - gained).abs() < 1e-4 * energy, "{:?} for {}", world.get_wall_work(), gained);
+        assert!(world.get_wall_work().iter().enumerate().all(|(i, &w)| i == 1 || w == 0.0));
+    }
 
-    pub fn draw(&self, window: &mut RenderWindow) {
-        for ball in &self.balls {
-            ball.draw(window);
+
+    #[test]
+    fn thermal_walls_stepped_backwards_keep_balls_inside() {
+        let scene = crate::scene::Scene::parse("(seed: Some(4), container: Walls([
+               (position: (x: 0.0, y: 0.0), normal: (x: 1.0, y: 0.0), temperature: Some(10000.0)),
+               (position: (x: 400.0, y: 0.0), normal: (x: -1.0, y: 0.0), temperature: Some(10000.0)),
+               (position: (x: 0.0, y: 0.0), normal: (x: 0.0, y: 1.0), temperature: Some(10000.0)),
+               (position: (x: 0.0, y: 400.0), normal: (x: 0.0, y: -1.0), temperature: Some(10000.0)),
+            ]),
+            spawn: [(count: 60, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 5.0, mass: 1.0,
+                     speed: MaxwellBoltzmann(temperature: 10000.0))])").unwrap();
+        let mut world = scene.build().unwrap();
+        for _ in 0..300 {
+            world.update(-1.0 / 60.0);
+            for ball in &world.balls {
+                let p = ball.get_position();
+                let r = ball.circle.radius - 1e-3;
+                assert!(p.x >= r && p.x <= 400.0 - r && p.y >= r && p.y <= 400.0 - r, "escaped to {:?}", p);
+            }
         }
-        self.draw_walls(window);
+        assert!(world.collision_total(CollisionKind::Wall) > 100);
     }
 
 
+    #[test]
+    fn thermal_walls_bring_the_gas_to_their_temperature() {
+        let scene = crate::scene::Scene::parse("(seed: Some(6), container: Walls([
+               (position: (x: 0.0, y: 0.0), normal: (x: 1.0, y: 0.0), temperature: Some(20000.0)),
+               (position: (x: 400.0, y: 0.0), normal: (x: -1.0, y: 0.0), temperature: Some(20000.0)),
+               (position: (x: 0.0, y: 0.0), normal: (x: 0.0, y: 1.0), temperature: Some(20000.0)),
+               (position: (x: 0.0, y: 400.0), normal: (x: 0.0, y: -1.0), temperature: Some(20000.0)),
+            ]),
+            spawn: [(count: 60, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 5.0, mass: 1.0,
+                     speed: MaxwellBoltzmann(temperature: 2000.0))])").unwrap();
+        let mut world = scene.build().unwrap();
+        let energy = world.kinetic_energy();
+        // In two dimensions a ball in equilibrium at temperature T has a mean kinetic energy of T
+        let mut mean = 0.0;
+        for i in 0..600 {
+            world.update(1.0 / 60.0);
+            if i >= 300 {
+                mean += world.kinetic_energy() / 60.0 / 300.0;
+            }
+        }
+        assert!((mean - 20000.0).abs() < 2000.0, "mean kinetic energy per ball {}", mean);
+        // The walls don't move, so all of the energy the gas gained is heat
+        let heat: f64 = world.get_wall_heat().iter().sum();
+        let gain = world.kinetic_energy() - energy;
+        assert!((heat - gain).abs() < 1e-4 * world.kinetic_energy(), "heat {} but gained {}", heat, gain);
+    }
 
-    fn draw_walls(&self, window: &mut RenderWindow) {
-        let thickness = 10.0;
-        let rect = self.bounding_rect();
-        let mut shape = RectangleShape::with_size(Vector2f{ x: rect.width, y: thickness});
-        shape.set_fill_color(&Color::rgb(125, 125, 125));
 
-        shape.set_position(Vector2f{x: rect.left, y: rect.top + rect.height});
-        window.draw(&shape);
+    #[test]
+    fn elastic_box_does_not_drift() {
+        let mut world = random_world(12, 80);
+        for _ in 0..300 {
+            world.update(1.0 / 60.0);
+        }
+        let drift = world.drift().unwrap();
+        assert!(drift.largest() < 1e-4, "{:?}", drift);
+        assert!(world.collision_total(CollisionKind::Ball) > 100);
+    }
 
-        shape.set_position(Vector2f{x: rect.left, y: rect.top - thickness});
-        window.draw(&shape);
 
-        shape.set_size(Vector2f{x: thickness, y: rect.height});
+    #[test]
+    #[should_panic(expected = "exceed the tolerance")]
+    fn inelastic_box_trips_the_conservation_check() {
+        let scene = crate::scene::Scene::parse("(seed: Some(3), container: Box((left: 0.0, top: 0.0, width: 400.0, height: 400.0)),
+            spawn: [(count: 60, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 8.0, mass: 1.0,
+                     restitution: 0.5, speed: Uniform(min: 50.0, max: 300.0))])").unwrap();
+        let mut world = scene.build().unwrap();
+        world.set_conservation_check(ConservationCheck::Panic{tolerance: 1e-3});
+        for _ in 0..300 {
+            world.update(1.0 / 60.0);
+        }
+    }
 
-        shape.set_position(Vector2f{x: rect.left + rect.width, y: rect.top});
-        window.draw(&shape);
 
-        shape.set_position(Vector2f{x: rect.left - thickness, y: rect.top});
-        window.draw(&shape);
+    #[test]
+    fn area_is_enclosed_by_the_container() {
+        let area = |container: &str| {
+            let scene = crate::scene::Scene::parse(&format!("(seed: Some(1), container: {}, spawn: [])", container)).unwrap();
+            scene.build().unwrap().area()
+        };
+        let pi = std::f64::consts::PI;
+        let close = |a: Option<f64>, b: f64| (a.unwrap() - b).abs() < 1e-4 * b;
+        assert!(close(area("Box((left: 0.0, top: 0.0, width: 300.0, height: 200.0))"), 60000.0));
+        assert!(close(area("Circle(centre: (x: 50.0, y: -20.0), radius: 100.0)"), pi * 10000.0));
+        assert!(close(area("Stadium(centre: (x: 300.0, y: 200.0), length: 200.0, radius: 100.0)"), 40000.0 + pi * 10000.0));
+        assert!(close(area("Polygon([(x: 0.0, y: 0.0), (x: 400.0, y: 0.0), (x: 400.0, y: 300.0)])"), 60000.0));
+        // A slanted plane cuts a corner off the box
+        assert!(close(area("Walls([(position: (x: 0.0, y: 0.0), normal: (x: 1.0, y: 0.0)),
+                                  (position: (x: 100.0, y: 0.0), normal: (x: -1.0, y: 0.0)),
+                                  (position: (x: 0.0, y: 0.0), normal: (x: 0.0, y: 1.0)),
+                                  (position: (x: 0.0, y: 100.0), normal: (x: 0.0, y: -1.0)),
+                                  (position: (x: 50.0, y: 0.0), normal: (x: -1.0, y: 1.0))])"), 10000.0 - 1250.0));
+        // Nothing encloses a single segment
+        assert_eq!(area("Walls([(start: (x: 0.0, y: 0.0), end: (x: 100.0, y: 0.0))])"), None);
     }
 
 
-    pub fn get_balls(&self) -> &Vec<Ball>{
-        &self.balls
+    #[test]
+    fn pressure_of_a_dilute_gas_is_ideal() {
+        let scene = crate::scene::Scene::parse("(seed: Some(8), container: Box((left: 0.0, top: 0.0, width: 400.0, height: 400.0)),
+            spawn: [(count: 200, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 2.0, mass: 1.0,
+                     speed: MaxwellBoltzmann(temperature: 10000.0))])").unwrap();
+        let mut world = scene.build().unwrap();
+        world.set_pressure_window(10.0);
+        for _ in 0..600 {
+            world.update(1.0 / 60.0);
+        }
+        let predicted = world.predicted_pressure().unwrap();
+        assert!(world.packing_fraction().unwrap() < 0.02);
+        let pressure = world.pressure().unwrap();
+        assert!((pressure - predicted.ideal_gas).abs() < 0.1 * predicted.ideal_gas, "{} for {:?}", pressure, predicted);
+        for p in world.wall_pressures().unwrap() {
+            assert!((p - predicted.ideal_gas).abs() < 0.2 * predicted.ideal_gas, "{} for {:?}", p, predicted);
+        }
     }
 
 
-    // The rectangle representing the box in which the particles are contained
-    fn bounding_rect(&self) -> FloatRect {
-        let left = self.walls[0].position.x;
-        let top = self.walls[2].position.y;
-        let width = self.walls[1].position.x - left;
-        let height = self.walls[3].position.y - top;
-        FloatRect{left, top, width, height}
+    #[test]
+    fn periodic_world_has_no_pressure() {
+        let scene = crate::scene::Scene::parse("(seed: Some(2), container: Box((left: 0.0, top: 0.0, width: 200.0, height: 200.0)),
+            periodic: (true, true),
+            spawn: [(count: 20, region: (left: 0.0, top: 0.0, width: 200.0, height: 200.0), radius: 3.0, mass: 1.0,
+                     speed: Fixed(100.0))])").unwrap();
+        let mut world = scene.build().unwrap();
+        world.update(1.0 / 60.0);
+        assert_eq!(world.pressure(), None);
+        assert_eq!(world.area(), Some(40000.0));
     }
 
 
-    fn rect_to_planes(rect: &FloatRect) -> [Plane; 4] {
-        [
-            Plane{position: Vector2f{x: rect.left, y: 0.0}, normal: Vector2f{x: 1.0, y: 0.0}},
-            Plane{position: Vector2f{x: rect.left + rect.width, y: 0.0}, normal: Vector2f{x: -1.0, y: 0.0}},
-            Plane{position: Vector2f{x: 0.0, y: rect.top}, normal: Vector2f{x: 0.0, y: 1.0}},
-            Plane{position: Vector2f{x: 0.0, y: rect.top + rect.height}, normal: Vector2f{x: 0.0, y: -1.0}}
-        ]
+    #[test]
+    fn logging_does_not_change_the_run() {
+        let scene = crate::scene::Scene::parse("(seed: Some(10), container: Box((left: 0.0, top: 0.0, width: 400.0, height: 400.0)),
+            spawn: [(count: 60, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 8.0, mass: 1.0,
+                     speed: Uniform(min: 50.0, max: 300.0))])").unwrap();
+        let (mut logged, mut unlogged) = (scene.build().unwrap(), scene.build().unwrap());
+        let log = TimeSeriesLog::new(Box::new(std::io::sink()), TimeSeriesLog::default_observables(), 0.05, &logged, &scene).unwrap();
+        logged.set_log(log);
+        for i in 0..240 {
+            // Steps that don't line up with the samples, forwards and then backwards
+            let dt = if i < 160 { 0.013 } else { -0.021 };
+            logged.update(dt);
+            unlogged.update(dt);
+        }
+        assert_eq!(logged.time, unlogged.time);
+        for (a, b) in logged.balls.iter().zip(&unlogged.balls) {
+            assert_eq!((a.get_position(), a.velocity), (b.get_position(), b.velocity));
+        }
     }
 
 
+    #[test]
+    fn collision_events_conserve_momentum() {
+        let scene = crate::scene::Scene::parse("(seed: Some(13), container: Box((left: 0.0, top: 0.0, width: 400.0, height: 400.0)),
+            spawn: [(count: 30, region: (left: 0.0, top: 0.0, width: 400.0, height: 200.0), radius: 8.0, mass: 1.0,
+                     speed: Uniform(min: 50.0, max: 300.0)),
+                    (count: 30, region: (left: 0.0, top: 200.0, width: 400.0, height: 200.0), radius: 12.0, mass: 3.5,
+                     friction: 0.4, speed: Uniform(min: 50.0, max: 300.0))])").unwrap();
+        let mut world = scene.build().unwrap();
+        let (_, receiver) = world.subscribe_channel();
+        for _ in 0..120 {
+            world.update(1.0 / 60.0);
+        }
+        let events: Vec<CollisionEvent> = receiver.try_iter().filter(|e| e.kind == CollisionKind::Ball).collect();
+        assert!(events.len() > 50);
+        for event in events {
+            let (m_a, m_b) = (world.balls[event.ball_index].get_mass(), world.balls[event.collider_index].get_mass());
+            let gained = (event.after.velocity - event.before.velocity) * m_a;
+            let given = (event.collider_after.unwrap().velocity - event.collider_before.unwrap().velocity) * m_b;
+            let scale = m_a * vector_math::length_squared(&event.before.velocity).sqrt()
+                      + m_b * vector_math::length_squared(&event.collider_before.unwrap().velocity).sqrt();
+            let total = gained + given;
+            assert!(vector_math::length_squared(&total).sqrt() < 1e-5 * scale, "momentum changed by {:?}", total);
+            let error = gained - event.impulse;
+            assert!(vector_math::length_squared(&error).sqrt() < 1e-5 * scale, "{:?} but gained {:?}", event.impulse, gained);
+        }
+    }
+
+
+    #[test]
+    fn exact_integrator_returns_exactly() {
+        for &periodic in &["(false, false)", "(true, true)"] {
+            let scene = crate::scene::Scene::parse(&format!("(seed: Some(5), container: Box((left: 0.0, top: 0.0, width: 400.0, height: 400.0)),
+                periodic: {},
+                integrator: Exact(potential: Wca(epsilon: 1000.0), step: 0.0005, resolution: 0.00001),
+                spawn: [(count: 60, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 8.0, mass: 1.0,
+                         speed: MaxwellBoltzmann(temperature: 10000.0))])", periodic)).unwrap();
+            let mut world = scene.build().unwrap();
+            let reversal = crate::reversal::reverse_and_return(&mut world, &[1.0 / 60.0; 60]);
+            assert!(reversal.is_exact(), "{} periodic: off by up to {}", periodic, reversal.max_displacement());
+        }
+    }
+
+
+    #[test]
+    fn reversed_velocities_retrace_the_path() {
+        let scene = crate::scene::Scene::parse("(seed: Some(6), container: Box((left: 0.0, top: 0.0, width: 400.0, height: 400.0)),
+            periodic: (true, false),
+            integrator: Exact(potential: LennardJones(epsilon: 1000.0, cutoff: 2.5), step: 0.0005, resolution: 0.00001),
+            spawn: [(count: 60, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 8.0, mass: 1.0,
+                     speed: MaxwellBoltzmann(temperature: 10000.0))])").unwrap();
+        let mut world = scene.build().unwrap();
+        world.quantize();
+        let positions = |world: &World| world.balls.iter().map(Ball::get_position).collect::<Vec<_>>();
+        let mut path = vec![positions(&world)];
+        // Updates of exactly 40 steps, so that the path is compared at the same steps each way
+        for _ in 0..40 {
+            world.update(0.02);
+            path.push(positions(&world));
+        }
+        world.reverse_velocities();
+        for expected in path.iter().rev().skip(1) {
+            world.update(0.02);
+            assert_eq!(&positions(&world), expected);
+        }
+    }
+
+
+    #[test]
+    fn events_follow_balls_removed_and_added_mid_run() {
+        let scene = crate::scene::Scene::parse("(seed: Some(14), container: Box((left: 0.0, top: 0.0, width: 400.0, height: 400.0)),
+            spawn: [(count: 60, region: (left: 0.0, top: 0.0, width: 400.0, height: 400.0), radius: 8.0, mass: 1.0,
+                     speed: Uniform(min: 100.0, max: 300.0))])").unwrap();
+        let mut world = scene.build().unwrap();
+        let events = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let sink = events.clone();
+        let id = world.subscribe(move |event: &CollisionEvent| sink.borrow_mut().push(*event));
+
+        for i in 0..120 {
+            if i == 40 {
+                // Every ball after the first moves down an index, and the new ball takes the last one
+                world.remove_ball(0);
+                let ball = (1..20).flat_map(|i| (1..20).map(move |j| Vector2f{x: 20.0 * i as f32, y: 20.0 * j as f32}))
+                    .map(|p| Ball::new(p, Vector2f{x: 250.0, y: -150.0}, 8.0, 1.0))
+                    .find(|ball| !world.balls.iter().any(|b| b.intersects(ball)))
+                    .unwrap();
+                assert_eq!(world.add_ball(ball), 59);
+            }
+            // Each event must start from the velocities the balls it names had after their last collision
+            let mut velocities: Vec<Vector2f> = world.balls.iter().map(|b| b.velocity).collect();
+            world.update(1.0 / 60.0);
+            for event in events.borrow_mut().drain(..) {
+                assert_eq!(event.before.velocity, velocities[event.ball_index]);
+                velocities[event.ball_index] = event.after.velocity;
+                if event.kind == CollisionKind::Ball {
+                    assert_eq!(event.collider_before.unwrap().velocity, velocities[event.collider_index]);
+                    velocities[event.collider_index] = event.collider_after.unwrap().velocity;
+                }
+            }
+            assert!(world.balls.iter().zip(&velocities).all(|(b, v)| b.velocity == *v));
+            assert_separated(&world, 400.0);
+        }
+        assert!(world.collision_total(CollisionKind::Ball) > 100);
+
+        // A wall added across the middle is hit, under its index, until the subscription ends
+        let wall = world.add_wall(Segment::new(Vector2f{x: 0.0, y: 200.0}, Vector2f{x: 400.0, y: 200.0}));
+        world.update(0.5);
+        assert!(events.borrow().iter().any(|e| e.kind == CollisionKind::Wall && e.collider_index == wall));
+        assert!(world.unsubscribe(id));
+        assert!(!world.unsubscribe(id));
+        events.borrow_mut().clear();
+        world.update(0.5);
+        assert!(events.borrow().is_empty());
+    }
+
+
+    #[test]
+    fn inelastic_collision_keeps_half_the_approach_speed() {
+        let mut world = World::empty();
+        let mut a = Ball::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 100.0, y: 0.0}, 10.0, 1.0);
+        let mut b = Ball::new(Vector2f{x: 100.0, y: 0.0}, Vector2f{x: -50.0, y: 0.0}, 10.0, 2.0);
+        a.set_restitution(0.5);
+        b.set_restitution(0.5);
+        world.add_ball(a);
+        world.add_ball(b);
+        world.update(1.0);
+        assert_eq!(world.collision_total(CollisionKind::Ball), 1);
+        let (a, b) = (&world.balls[0], &world.balls[1]);
+        // They approached at 150 and part at 75, with no total momentum before or after
+        assert!((b.velocity.x - a.velocity.x - 75.0).abs() < 1e-3, "{:?} and {:?}", a.velocity, b.velocity);
+        assert!((a.velocity.x + 2.0 * b.velocity.x).abs() < 1e-3);
+        assert!(a.velocity.y == 0.0 && b.velocity.y == 0.0);
+    }
+
+
+    #[test]
+    fn combine_rules_pick_the_coefficient() {
+        for &(rule, expected) in &[(CombineRule::Min, 0.5), (CombineRule::Product, 0.4), (CombineRule::Average, 0.65)] {
+            // Between balls of 0.8 and 0.5, meeting head on at a relative speed of 200
+            let mut world = World::empty();
+            world.set_restitution_rule(rule);
+            let mut a = Ball::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 100.0, y: 0.0}, 10.0, 1.0);
+            let mut b = Ball::new(Vector2f{x: 100.0, y: 0.0}, Vector2f{x: -100.0, y: 0.0}, 10.0, 1.0);
+            a.set_restitution(0.8);
+            b.set_restitution(0.5);
+            world.add_ball(a);
+            world.add_ball(b);
+            world.update(1.0);
+            let parting = world.balls[1].velocity.x - world.balls[0].velocity.x;
+            assert!((parting - 200.0 * expected).abs() < 1e-3, "{:?} parted at {}", rule, parting);
+
+            // Between a ball of 0.8 and a wall of 0.5
+            let mut world = World::empty();
+            world.set_restitution_rule(rule);
+            world.add_wall(Plane{restitution: 0.5, ..Plane::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 1.0, y: 0.0})});
+            let mut ball = Ball::new(Vector2f{x: 50.0, y: 0.0}, Vector2f{x: -100.0, y: 0.0}, 10.0, 1.0);
+            ball.set_restitution(0.8);
+            world.add_ball(ball);
+            world.update(1.0);
+            assert!((world.balls[0].velocity.x - 100.0 * expected).abs() < 1e-3, "{:?} left at {:?}", rule, world.balls[0].velocity);
+        }
+    }
+
+
+    #[test]
+    fn inelastic_ball_comes_to_rest_on_the_floor() {
+        // Without a guard the bounces come ever faster and update never returns
+        for &guard in &[CollapseGuard::default(), CollapseGuard::MinimumSpeed(1.0)] {
+            let scene = crate::scene::Scene::parse("(seed: Some(1), container: Box((left: 0.0, top: 0.0, width: 100.0, height: 100.0)),
+                balls: [(position: (x: 50.0, y: 50.0), velocity: (x: 0.0, y: 0.0), radius: 5.0, mass: 1.0, restitution: 0.5)],
+                field: Gravity((x: 0.0, y: 100.0)))").unwrap();
+            let mut world = scene.build().unwrap();
+            assert_eq!(world.get_collapse_guard(), CollapseGuard::default());
+            world.set_collapse_guard(guard);
+            for _ in 0..600 {
+                world.update(1.0 / 60.0);
+            }
+            let ball = &world.balls[0];
+            assert!(ball.get_position().y > 94.0 && ball.get_position().y <= 95.0 + 1e-3, "{:?} at {:?}", guard, ball.get_position());
+            assert!(ball.velocity.y.abs() < 5.0, "{:?} still moving at {:?}", guard, ball.velocity);
+        }
+    }
+
+
+    #[test]
+    fn soft_walls_push_back_balls_that_got_past_them() {
+        let floor = Wall::Plane(Plane::new(Vector2f{x: 0.0, y: 0.0}, Vector2f{x: 0.0, y: 1.0}));
+        let obstacle = Obstacle::new(Vector2f{x: 1000.0, y: 1000.0}, 20.0);
+        let balls: Vec<Ball> = [(0.0, 4.0), (100.0, 0.0), (200.0, -2.0), (300.0, -20.0), (1000.0, 1015.0), (1000.0, 990.0)]
+            .iter()
+            .map(|&(x, y)| Ball::new(Vector2f{x, y}, Vector2f::default(), 8.0, 1.0))
+            .collect();
+        for potential in [Potential::Wca{epsilon: 1.0}, Potential::LennardJones{epsilon: 1.0, cutoff: 2.5},
+                          Potential::Harmonic{stiffness: 1.0}] {
+            let accelerations = potential::accelerations(&balls, &[floor], &[obstacle], &potential, &Field::None,
+                                                         &Periodic::default(), &[]);
+            for (ball, acceleration) in balls[..4].iter().zip(&accelerations) {
+                assert!(acceleration.x == 0.0 && acceleration.y > 0.0 && acceleration.y.is_finite(),
+                        "{:?} at {:?}: {:?}", potential, ball.get_position(), acceleration);
+            }
+            assert!(accelerations[4].y > 0.0 && accelerations[4].y.is_finite() && accelerations[4].x == 0.0);
+            assert!(accelerations[5].y < 0.0 && accelerations[5].y.is_finite() && accelerations[5].x == 0.0);
+            assert!(potential::interaction_energy(&balls, &[floor], &[obstacle], &potential, &Periodic::default(), &[]).is_finite());
+        }
+    }
 }